@@ -178,7 +178,14 @@ def get_move(game_state: chess.Board, limit: Limit) -> str:
             except IndexError:
                 ...
 
-    return mcts_rust.search_tree(game_state.fen(), limit.time, temperature, processes)
+    # The game so far lets the search see repetitions
+    return mcts_rust.search_tree(
+        game_state.root().fen(),
+        limit.time,
+        temperature,
+        processes,
+        moves=[move.uci() for move in game_state.move_stack],
+    ).best_move
 
 
 def auto_check():
@@ -0,0 +1,84 @@
+use chess::{Board, ChessMove, File, Piece, Rank, Square};
+use rand::{distributions::WeightedIndex, prelude::*, thread_rng};
+use std::{collections::HashMap, fs, io, path::Path};
+
+use crate::{from_king_takes_rook, polyglot::polyglot_key};
+
+const ENTRY_SIZE: usize = 16;
+
+// Opening book read from Polyglot formatted entries, keyed by `polyglot_key`
+pub struct Book {
+    entries: HashMap<u64, Vec<(ChessMove, u16)>>,
+}
+
+// Polyglot packs a move as to file, to row, from file, from row and promotion,
+// three bits each from the lowest bits up
+fn decode_move(encoded: u16) -> ChessMove {
+    let square = |shift: u16| {
+        let file = File::from_index(((encoded >> shift) & 7) as usize);
+        let rank = Rank::from_index(((encoded >> (shift + 3)) & 7) as usize);
+        Square::make_square(rank, file)
+    };
+    let promotion = match (encoded >> 12) & 7 {
+        1 => Some(Piece::Knight),
+        2 => Some(Piece::Bishop),
+        3 => Some(Piece::Rook),
+        4 => Some(Piece::Queen),
+        _ => None,
+    };
+    ChessMove::new(square(6), square(0), promotion)
+}
+
+impl Book {
+    pub fn from_polyglot<P: AsRef<Path>>(path: P) -> io::Result<Book> {
+        let bytes = fs::read(path)?;
+        if bytes.len() % ENTRY_SIZE != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "polyglot book size is not a multiple of 16 bytes",
+            ));
+        }
+
+        let mut entries: HashMap<u64, Vec<(ChessMove, u16)>> = HashMap::new();
+        for entry in bytes.chunks_exact(ENTRY_SIZE) {
+            let mut key = [0; 8];
+            key.copy_from_slice(&entry[0..8]);
+            let encoded = u16::from_be_bytes([entry[8], entry[9]]);
+            let weight = u16::from_be_bytes([entry[10], entry[11]]);
+            // The remaining 4 bytes are learning data, which is unused
+            entries
+                .entry(u64::from_be_bytes(key))
+                .or_default()
+                .push((decode_move(encoded), weight));
+        }
+        Ok(Book { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Legal book move for the position, picked at random by weight
+    pub fn probe(&self, board: &Board) -> Option<ChessMove> {
+        let candidates: Vec<(ChessMove, u16)> = self
+            .entries
+            .get(&polyglot_key(board))?
+            .iter()
+            // Castling is stored as the king capturing its own rook
+            .map(|(action, weight)| (from_king_takes_rook(board, *action), *weight))
+            .filter(|(action, _)| board.legal(*action))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        // All zero weights still leave every move playable
+        let weights = candidates.iter().map(|(_, weight)| (*weight).max(1));
+        let index = WeightedIndex::new(weights).ok()?;
+        Some(candidates[index.sample(&mut thread_rng())].0)
+    }
+}
@@ -1,13 +1,256 @@
-use chess::{BitBoard, Board, BoardStatus, ChessMove, Color, MoveGen, Piece, Square};
+use chess::{
+    get_adjacent_files, get_bishop_moves, get_file, get_king_moves, get_knight_moves,
+    get_pawn_attacks, get_rank, get_rook_moves, BitBoard, Board, BoardStatus, ChessMove, Color,
+    MoveGen, Piece, Rank, Square, ALL_FILES, ALL_SQUARES,
+};
 use ordered_float::OrderedFloat;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs, io,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
+const PIECES: [Piece; 5] = [
+    Piece::Pawn,
+    Piece::Bishop,
+    Piece::Knight,
+    Piece::Rook,
+    Piece::Queen,
+];
+
+// a1, c1, ... the squares a dark-squared bishop can reach
+const DARK_SQUARES: BitBoard = BitBoard(0xAA55_AA55_AA55_AA55);
+
+// Source of evaluator versions, unique across every evaluator in the process
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+// On-disk form of an evaluator's weights, piece values are in `PIECES` order
+#[derive(Serialize, Deserialize)]
+struct Weights {
+    piece_values: [f32; 5],
+    eg_piece_values: [f32; 5],
+    king_ring_values: [f32; 4],
+    center_control_value: f32,
+    checker_penalty: f32,
+    trade_weight: f32,
+    repetition_factor: f32,
+    passed_pawn_values: [f32; 2],
+    doubled_pawn_penalty: f32,
+    isolated_pawn_penalty: f32,
+    mobility_weight: f32,
+    bishop_pair_values: [f32; 2],
+    king_shield_penalty: f32,
+    king_attack_weight: f32,
+    rook_file_values: [f32; 2],
+    rook_seventh_value: f32,
+    hanging_piece_factor: f32,
+    connected_pawn_value: f32,
+    phalanx_pawn_value: f32,
+    draw_claim_value: Option<f32>,
+    prior_eval_mix: f32,
+    check_prior_bonus: f32,
+    capture_prior_factor: f32,
+    check_prior_factor: f32,
+    uniform_priors: bool,
+}
+
+// Indices into `Evaluator::parameters()`. Groups of values give the index of
+// their first entry, piece values in PIECES order and pairs as (middlegame, endgame)
+pub const PIECE_VALUES: usize = 0;
+pub const KING_RING_VALUES: usize = 5;
+pub const CENTER_CONTROL: usize = 9;
+pub const CHECKER_PENALTY: usize = 10;
+pub const TRADE_WEIGHT: usize = 11;
+pub const REPETITION_FACTOR: usize = 12;
+pub const PASSED_PAWN_VALUES: usize = 13;
+pub const EG_PIECE_VALUES: usize = 15;
+pub const DOUBLED_PAWN_PENALTY: usize = 20;
+pub const ISOLATED_PAWN_PENALTY: usize = 21;
+pub const MOBILITY_WEIGHT: usize = 22;
+pub const BISHOP_PAIR_VALUES: usize = 23;
+pub const KING_SHIELD_PENALTY: usize = 25;
+pub const KING_ATTACK_WEIGHT: usize = 26;
+pub const ROOK_FILE_VALUES: usize = 27;
+pub const ROOK_SEVENTH: usize = 29;
+pub const HANGING_PIECE_FACTOR: usize = 30;
+pub const CONNECTED_PAWN: usize = 31;
+pub const PHALANX_PAWN: usize = 32;
+pub const PARAMETER_COUNT: usize = 33;
+
+// Value of being checkmated for the side to move, worse than losing a full
+// army of material (39 pawns)
+pub const MATE_VALUE: f32 = -39.0;
+// Prior score of a move that checkmates, from the mated side's view. Priors
+// score moves by piece count rather than material, so this only has to beat
+// the largest count difference (16 pieces), not MATE_VALUE
+const PRIOR_MATE_SCORE: f32 = -16.0;
+
+// Prior score added to a capture that loses material once the exchange is played
+// out, enough to rank it below quiet moves, like losing a piece outright
+const LOSING_CAPTURE_PRIOR_PENALTY: f32 = 2.0;
+// Prior boosts of captures that don't lose material and of checks, see
+// `set_forcing_prior_factors`
+const DEFAULT_CAPTURE_PRIOR_FACTOR: f32 = 1.5;
+const DEFAULT_CHECK_PRIOR_FACTOR: f32 = 1.5;
+// Stands in for the king in exchanges, so capturing with it into a defended
+// square is never worth it
+const SEE_KING_VALUE: f32 = 100.0;
+
+// Phase weight of all minor and major pieces in the starting position. A queen,
+// a rook and two minors each comes to 16, two rooks and two minors each to 12
+const TOTAL_PHASE: f32 = 24.0;
+// Game phase at or below which a position counts as an endgame, about a rook
+// and a minor piece each
+const ENDGAME_PHASE: f32 = 0.25;
+// Most pawns left on the board for a position to count as open
+const OPEN_MAX_PAWNS: u32 = 10;
+// Most pawns blocked head-on by an enemy pawn for a position to count as open
+const OPEN_MAX_LOCKED_PAWNS: u32 = 1;
+
+// For each square, the squares ahead of a pawn on it on its own and the adjacent files
+fn passed_pawn_masks() -> [[BitBoard; 64]; 2] {
+    let mut masks = [[BitBoard::new(0); 64]; 2];
+    for square in ALL_SQUARES.iter() {
+        let file = square.get_file().to_index() as i32;
+        let rank = square.get_rank().to_index() as i32;
+        for other in ALL_SQUARES.iter() {
+            let other_file = other.get_file().to_index() as i32;
+            let other_rank = other.get_rank().to_index() as i32;
+            if (other_file - file).abs() > 1 {
+                continue;
+            }
+            if other_rank > rank {
+                masks[Color::White.to_index()][square.to_index()] |= BitBoard::from_square(*other);
+            }
+            if other_rank < rank {
+                masks[Color::Black.to_index()][square.to_index()] |= BitBoard::from_square(*other);
+            }
+        }
+    }
+    masks
+}
+
+// For each square, the squares one and two ranks ahead of a king on it on its
+// own and the adjacent files, where its pawn shield stands
+fn king_shield_masks() -> [[BitBoard; 64]; 2] {
+    let mut masks = [[BitBoard::new(0); 64]; 2];
+    for square in ALL_SQUARES.iter() {
+        let file = square.get_file().to_index() as i32;
+        let rank = square.get_rank().to_index() as i32;
+        for other in ALL_SQUARES.iter() {
+            let other_file = other.get_file().to_index() as i32;
+            let other_rank = other.get_rank().to_index() as i32;
+            if (other_file - file).abs() > 1 {
+                continue;
+            }
+            if other_rank > rank && other_rank <= rank + 2 {
+                masks[Color::White.to_index()][square.to_index()] |= BitBoard::from_square(*other);
+            }
+            if other_rank < rank && other_rank >= rank - 2 {
+                masks[Color::Black.to_index()][square.to_index()] |= BitBoard::from_square(*other);
+            }
+        }
+    }
+    masks
+}
+
+// Neither side can mate, lone kings, a single minor piece or one bishop each on
+// the same color squares
+pub fn insufficient_material(state: &Board) -> bool {
+    let heavy = state.pieces(Piece::Pawn) | state.pieces(Piece::Rook) | state.pieces(Piece::Queen);
+    if heavy.popcnt() > 0 {
+        return false;
+    }
+    let bishops = state.pieces(Piece::Bishop);
+    let minors = bishops | state.pieces(Piece::Knight);
+    match minors.popcnt() {
+        0 | 1 => true,
+        2 => {
+            let white_bishops = bishops & state.color_combined(Color::White);
+            let dark_bishops = bishops & DARK_SQUARES;
+            bishops.popcnt() == 2
+                && white_bishops.popcnt() == 1
+                && (dark_bishops.popcnt() == 0 || dark_bishops.popcnt() == 2)
+        }
+        _ => false,
+    }
+}
+
+#[derive(Clone)]
 pub struct Evaluator {
     piece_value_map: HashMap<Piece, f32>,
+    // Piece values once only kings and pawns remain, material is tapered between the two
+    eg_piece_value_map: HashMap<Piece, f32>,
+    // Bonus for the king being in the center, inner, mid and outer rings in the endgame
+    king_ring_values: [f32; 4],
+    center_control_value: f32,
+    checker_penalty: f32,
+    // Bonus per traded piece for the side ahead in material
+    trade_weight: f32,
+    // Share of the value kept when the position repeats, pulling it toward a draw
+    repetition_factor: f32,
+    // Bonus for a passed pawn about to promote in the middlegame and endgame,
+    // scaled down quadratically the further it is from promotion
+    passed_pawn_values: [f32; 2],
+    // Penalty for each extra pawn on a file already holding a friendly pawn
+    doubled_pawn_penalty: f32,
+    // Penalty for a pawn with no friendly pawns on the adjacent files
+    isolated_pawn_penalty: f32,
+    // Bonus per legal move the side to move has over its opponent
+    mobility_weight: f32,
+    // Bonus for holding both bishops in the middlegame and endgame
+    bishop_pair_values: [f32; 2],
+    // Middlegame penalty for each file in front of the king without a shield pawn
+    king_shield_penalty: f32,
+    // Middlegame penalty for each attack by an enemy piece on the king or its neighbours
+    king_attack_weight: f32,
+    // Bonus for a rook on a file without friendly pawns, and on one without any pawns
+    rook_file_values: [f32; 2],
+    // Bonus for a rook on its seventh rank
+    rook_seventh_value: f32,
+    // Share of a minor or major piece's value lost when it is attacked by a
+    // cheaper piece, or attacked and undefended
+    hanging_piece_factor: f32,
+    // Bonus for each pawn defended by a friendly pawn
+    connected_pawn_value: f32,
+    // Bonus for each pawn with a friendly pawn beside it on the same rank
+    phalanx_pawn_value: f32,
+    // Prior score of claiming an available draw, on the same scale as a move's
+    // piece difference for the opponent, None to never offer the claim
+    draw_claim_value: Option<f32>,
+    // Share of the full evaluation blended into the material scores behind the priors
+    prior_eval_mix: f32,
+    // Taken off the prior score of a move that gives check, in pieces, so forcing
+    // moves and checking promotions are explored early
+    check_prior_bonus: f32,
+    // Multiply the normalized prior of a capture that doesn't lose material, and
+    // of a check, before renormalizing, so forcing moves stand out. 1 for no boost
+    capture_prior_factor: f32,
+    check_prior_factor: f32,
+    // Give every legal move the same prior, ignoring the scores above
+    uniform_priors: bool,
     outer_ring: Vec<Square>,
     mid_ring: Vec<Square>,
     inner_ring: Vec<Square>,
     center: Vec<Square>,
+    // Squares that must be free of enemy pawns for a pawn to be passed, by color
+    passed_pawn_masks: [[BitBoard; 64]; 2],
+    king_shield_masks: [[BitBoard; 64]; 2],
+    // The king's square and its neighbours, by square
+    king_zone_masks: [BitBoard; 64],
+    // Changes whenever a weight does, so cached evaluations can be checked against it
+    version: u64,
+}
+
+impl Default for Evaluator {
+    fn default() -> Evaluator {
+        Evaluator::new()
+    }
 }
 
 impl Evaluator {
@@ -18,18 +261,509 @@ impl Evaluator {
         pvm.insert(Piece::Knight, 3.05);
         pvm.insert(Piece::Rook, 5.63);
         pvm.insert(Piece::Queen, 9.5);
+        let mut eg_pvm = HashMap::new();
+        eg_pvm.insert(Piece::Pawn, 1.2);
+        eg_pvm.insert(Piece::Bishop, 3.4);
+        eg_pvm.insert(Piece::Knight, 2.95);
+        eg_pvm.insert(Piece::Rook, 5.9);
+        eg_pvm.insert(Piece::Queen, 9.5);
         Evaluator {
             piece_value_map: pvm,
+            eg_piece_value_map: eg_pvm,
+            king_ring_values: [0.5, 0.25, -0.25, -0.5],
+            center_control_value: 0.25,
+            checker_penalty: 0.75,
+            trade_weight: 0.05,
+            repetition_factor: 0.5,
+            passed_pawn_values: [0.1, 1.0],
+            doubled_pawn_penalty: 0.2,
+            isolated_pawn_penalty: 0.15,
+            mobility_weight: 0.02,
+            bishop_pair_values: [0.3, 0.5],
+            king_shield_penalty: 0.15,
+            king_attack_weight: 0.05,
+            rook_file_values: [0.1, 0.25],
+            rook_seventh_value: 0.2,
+            hanging_piece_factor: 0.1,
+            connected_pawn_value: 0.1,
+            phalanx_pawn_value: 0.05,
+            draw_claim_value: Some(0.0),
+            prior_eval_mix: 0.0,
+            check_prior_bonus: 0.5,
+            capture_prior_factor: DEFAULT_CAPTURE_PRIOR_FACTOR,
+            check_prior_factor: DEFAULT_CHECK_PRIOR_FACTOR,
+            uniform_priors: false,
             outer_ring: BitBoard::new(18411139144890810879).collect(),
             mid_ring: BitBoard::new(35538699412471296).collect(),
             inner_ring: BitBoard::new(66125924401152).collect(),
             center: BitBoard::new(103481868288).collect(),
+            passed_pawn_masks: passed_pawn_masks(),
+            king_shield_masks: king_shield_masks(),
+            king_zone_masks: {
+                let mut masks = [BitBoard::new(0); 64];
+                for square in ALL_SQUARES.iter() {
+                    masks[square.to_index()] =
+                        get_king_moves(*square) | BitBoard::from_square(*square);
+                }
+                masks
+            },
+            version: next_version(),
+        }
+    }
+
+    // Baseline that only counts material
+    pub fn material_only() -> Evaluator {
+        let mut evaluator = Evaluator::new();
+        evaluator.king_ring_values = [0.0; 4];
+        evaluator.center_control_value = 0.0;
+        evaluator.checker_penalty = 0.0;
+        evaluator.trade_weight = 0.0;
+        evaluator.repetition_factor = 1.0;
+        evaluator.passed_pawn_values = [0.0; 2];
+        evaluator.doubled_pawn_penalty = 0.0;
+        evaluator.isolated_pawn_penalty = 0.0;
+        evaluator.mobility_weight = 0.0;
+        evaluator.bishop_pair_values = [0.0; 2];
+        evaluator.king_shield_penalty = 0.0;
+        evaluator.king_attack_weight = 0.0;
+        evaluator.rook_file_values = [0.0; 2];
+        evaluator.rook_seventh_value = 0.0;
+        evaluator.hanging_piece_factor = 0.0;
+        evaluator.connected_pawn_value = 0.0;
+        evaluator.phalanx_pawn_value = 0.0;
+        evaluator
+    }
+
+    // Baseline with the full evaluation but no idea which moves to search first
+    pub fn uniform_prior() -> Evaluator {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_uniform_priors(true);
+        evaluator
+    }
+
+    // Flat list of all tunable weights, in a fixed order
+    pub fn parameters(&self) -> Vec<f32> {
+        let mut parameters: Vec<f32> = PIECES.iter().map(|p| self.piece_value_map[p]).collect();
+        parameters.extend_from_slice(&self.king_ring_values);
+        parameters.push(self.center_control_value);
+        parameters.push(self.checker_penalty);
+        parameters.push(self.trade_weight);
+        parameters.push(self.repetition_factor);
+        parameters.extend_from_slice(&self.passed_pawn_values);
+        parameters.extend(PIECES.iter().map(|p| self.eg_piece_value_map[p]));
+        parameters.push(self.doubled_pawn_penalty);
+        parameters.push(self.isolated_pawn_penalty);
+        parameters.push(self.mobility_weight);
+        parameters.extend_from_slice(&self.bishop_pair_values);
+        parameters.push(self.king_shield_penalty);
+        parameters.push(self.king_attack_weight);
+        parameters.extend_from_slice(&self.rook_file_values);
+        parameters.push(self.rook_seventh_value);
+        parameters.push(self.hanging_piece_factor);
+        parameters.push(self.connected_pawn_value);
+        parameters.push(self.phalanx_pawn_value);
+        parameters
+    }
+
+    pub fn set_parameters(&mut self, parameters: &[f32]) {
+        assert_eq!(parameters.len(), self.parameters().len());
+        self.version = next_version();
+        let mut values = parameters.iter().copied();
+        for piece in PIECES.iter() {
+            self.piece_value_map.insert(*piece, values.next().unwrap());
+        }
+        for ring_value in self.king_ring_values.iter_mut() {
+            *ring_value = values.next().unwrap();
+        }
+        self.center_control_value = values.next().unwrap();
+        self.checker_penalty = values.next().unwrap();
+        self.trade_weight = values.next().unwrap();
+        self.repetition_factor = values.next().unwrap();
+        for passed_pawn_value in self.passed_pawn_values.iter_mut() {
+            *passed_pawn_value = values.next().unwrap();
+        }
+        for piece in PIECES.iter() {
+            self.eg_piece_value_map
+                .insert(*piece, values.next().unwrap());
+        }
+        self.doubled_pawn_penalty = values.next().unwrap();
+        self.isolated_pawn_penalty = values.next().unwrap();
+        self.mobility_weight = values.next().unwrap();
+        for bishop_pair_value in self.bishop_pair_values.iter_mut() {
+            *bishop_pair_value = values.next().unwrap();
+        }
+        self.king_shield_penalty = values.next().unwrap();
+        self.king_attack_weight = values.next().unwrap();
+        for rook_file_value in self.rook_file_values.iter_mut() {
+            *rook_file_value = values.next().unwrap();
+        }
+        self.rook_seventh_value = values.next().unwrap();
+        self.hanging_piece_factor = values.next().unwrap();
+        self.connected_pawn_value = values.next().unwrap();
+        self.phalanx_pawn_value = values.next().unwrap();
+        assert!(values.next().is_none());
+    }
+
+    // Write the weights as JSON, readable back with `load`
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let piece_values = |map: &HashMap<Piece, f32>| {
+            let mut values = [0.0; 5];
+            for (value, piece) in values.iter_mut().zip(PIECES.iter()) {
+                *value = map[piece];
+            }
+            values
+        };
+        let weights = Weights {
+            piece_values: piece_values(&self.piece_value_map),
+            eg_piece_values: piece_values(&self.eg_piece_value_map),
+            king_ring_values: self.king_ring_values,
+            center_control_value: self.center_control_value,
+            checker_penalty: self.checker_penalty,
+            trade_weight: self.trade_weight,
+            repetition_factor: self.repetition_factor,
+            passed_pawn_values: self.passed_pawn_values,
+            doubled_pawn_penalty: self.doubled_pawn_penalty,
+            isolated_pawn_penalty: self.isolated_pawn_penalty,
+            mobility_weight: self.mobility_weight,
+            bishop_pair_values: self.bishop_pair_values,
+            king_shield_penalty: self.king_shield_penalty,
+            king_attack_weight: self.king_attack_weight,
+            rook_file_values: self.rook_file_values,
+            rook_seventh_value: self.rook_seventh_value,
+            hanging_piece_factor: self.hanging_piece_factor,
+            connected_pawn_value: self.connected_pawn_value,
+            phalanx_pawn_value: self.phalanx_pawn_value,
+            draw_claim_value: self.draw_claim_value,
+            prior_eval_mix: self.prior_eval_mix,
+            check_prior_bonus: self.check_prior_bonus,
+            capture_prior_factor: self.capture_prior_factor,
+            check_prior_factor: self.check_prior_factor,
+            uniform_priors: self.uniform_priors,
+        };
+        fs::write(path, serde_json::to_string_pretty(&weights)?)
+    }
+
+    // Read weights written by `save`, a missing or non-finite weight is an error
+    pub fn load(path: &str) -> io::Result<Evaluator> {
+        Evaluator::from_json(&fs::read_to_string(path)?)
+    }
+
+    fn from_json(json: &str) -> io::Result<Evaluator> {
+        let weights: Weights = serde_json::from_str(json)?;
+        let mut evaluator = Evaluator::new();
+        evaluator.piece_value_map = PIECES.iter().copied().zip(weights.piece_values).collect();
+        evaluator.eg_piece_value_map = PIECES
+            .iter()
+            .copied()
+            .zip(weights.eg_piece_values)
+            .collect();
+        evaluator.king_ring_values = weights.king_ring_values;
+        evaluator.center_control_value = weights.center_control_value;
+        evaluator.checker_penalty = weights.checker_penalty;
+        evaluator.trade_weight = weights.trade_weight;
+        evaluator.repetition_factor = weights.repetition_factor;
+        evaluator.passed_pawn_values = weights.passed_pawn_values;
+        evaluator.doubled_pawn_penalty = weights.doubled_pawn_penalty;
+        evaluator.isolated_pawn_penalty = weights.isolated_pawn_penalty;
+        evaluator.mobility_weight = weights.mobility_weight;
+        evaluator.bishop_pair_values = weights.bishop_pair_values;
+        evaluator.king_shield_penalty = weights.king_shield_penalty;
+        evaluator.king_attack_weight = weights.king_attack_weight;
+        evaluator.rook_file_values = weights.rook_file_values;
+        evaluator.rook_seventh_value = weights.rook_seventh_value;
+        evaluator.hanging_piece_factor = weights.hanging_piece_factor;
+        evaluator.connected_pawn_value = weights.connected_pawn_value;
+        evaluator.phalanx_pawn_value = weights.phalanx_pawn_value;
+        evaluator.set_draw_claim_value(weights.draw_claim_value);
+        evaluator.set_prior_eval_mix(weights.prior_eval_mix);
+        evaluator.set_check_prior_bonus(weights.check_prior_bonus);
+        evaluator
+            .set_forcing_prior_factors(weights.capture_prior_factor, weights.check_prior_factor);
+        evaluator.set_uniform_priors(weights.uniform_priors);
+
+        let finite = evaluator.parameters().iter().all(|value| value.is_finite())
+            && weights.draw_claim_value.is_none_or(f32::is_finite)
+            && weights.prior_eval_mix.is_finite()
+            && weights.check_prior_bonus.is_finite()
+            && weights.capture_prior_factor.is_finite()
+            && weights.check_prior_factor.is_finite();
+        if !finite {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "weights must be finite",
+            ));
+        }
+        Ok(evaluator)
+    }
+
+    // Weighted average of every tunable parameter, alpha * a + (1 - alpha) * b
+    pub fn blend(a: &Evaluator, b: &Evaluator, alpha: f32) -> Evaluator {
+        let parameters: Vec<f32> = a
+            .parameters()
+            .iter()
+            .zip(b.parameters())
+            .map(|(pa, pb)| alpha * pa + (1.0 - alpha) * pb)
+            .collect();
+        let mut evaluator = a.clone();
+        evaluator.set_parameters(&parameters);
+        evaluator
+    }
+
+    // Central finite difference of evaluate with respect to each parameter
+    pub fn numeric_gradient(&self, board: &Board, epsilon: f32) -> Vec<f32> {
+        let parameters = self.parameters();
+        let mut evaluator = self.clone();
+        let mut gradient = vec![];
+        for i in 0..parameters.len() {
+            let mut shifted = parameters.clone();
+            shifted[i] = parameters[i] + epsilon;
+            evaluator.set_parameters(&shifted);
+            let plus = evaluator.evaluate(board);
+            shifted[i] = parameters[i] - epsilon;
+            evaluator.set_parameters(&shifted);
+            let minus = evaluator.evaluate(board);
+            gradient.push((plus - minus) / (2.0 * epsilon));
+        }
+        gradient
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn set_draw_claim_value(&mut self, value: Option<f32>) {
+        self.draw_claim_value = value;
+        self.version = next_version();
+    }
+
+    // 0 keeps the cheap material priors, 1 scores every move by a full evaluation
+    pub fn set_prior_eval_mix(&mut self, mix: f32) {
+        self.prior_eval_mix = mix.clamp(0.0, 1.0);
+        self.version = next_version();
+    }
+
+    // 0 scores checks like any other move
+    pub fn set_check_prior_bonus(&mut self, bonus: f32) {
+        self.check_prior_bonus = bonus;
+        self.version = next_version();
+    }
+
+    pub fn set_uniform_priors(&mut self, uniform: bool) {
+        self.uniform_priors = uniform;
+        self.version = next_version();
+    }
+
+    // 1 leaves the priors of captures and checks as scored, factors below 0 count as 0
+    pub fn set_forcing_prior_factors(&mut self, capture: f32, check: f32) {
+        self.capture_prior_factor = capture.max(0.0);
+        self.check_prior_factor = check.max(0.0);
+        self.version = next_version();
+    }
+
+    // 1 with all pieces on the board, falling to 0 once only kings and pawns remain
+    pub fn game_phase(state: &Board) -> f32 {
+        let phase = state.pieces(Piece::Knight).popcnt()
+            + state.pieces(Piece::Bishop).popcnt()
+            + 2 * state.pieces(Piece::Rook).popcnt()
+            + 4 * state.pieces(Piece::Queen).popcnt();
+        (phase as f32 / TOTAL_PHASE).min(1.0)
+    }
+
+    pub fn is_endgame(state: &Board) -> bool {
+        Evaluator::game_phase(state) <= ENDGAME_PHASE
+    }
+
+    // Few pawns left and hardly any of them locked against each other
+    pub fn is_open(state: &Board) -> bool {
+        let pawns = state.pieces(Piece::Pawn);
+        let white = pawns & state.color_combined(Color::White);
+        let black = pawns & state.color_combined(Color::Black);
+        let locked = BitBoard::new(white.0 << 8) & black;
+        pawns.popcnt() <= OPEN_MAX_PAWNS && locked.popcnt() <= OPEN_MAX_LOCKED_PAWNS
+    }
+
+    // Value of each piece type blended between the middlegame and endgame maps
+    // by the phase of the position
+    pub fn tapered_piece_values(&self, state: &Board) -> HashMap<Piece, f32> {
+        let phase = Evaluator::game_phase(state);
+        PIECES
+            .iter()
+            .map(|piece| (*piece, self.tapered_value(*piece, phase)))
+            .collect()
+    }
+
+    fn tapered_value(&self, piece: Piece, phase: f32) -> f32 {
+        self.piece_value_map[&piece] * phase + self.eg_piece_value_map[&piece] * (1.0 - phase)
+    }
+
+    // Passed pawn bonus for `color`, tapered between middlegame and endgame
+    fn passed_pawns(&self, state: &Board, color: Color, phase: f32) -> f32 {
+        let pawns = state.pieces(Piece::Pawn);
+        let enemy_pawns = pawns & state.color_combined(!color);
+        let value = self.passed_pawn_values[0] * phase + self.passed_pawn_values[1] * (1.0 - phase);
+        let mut bonus = 0.0;
+        for square in pawns & state.color_combined(color) {
+            if (self.passed_pawn_masks[color.to_index()][square.to_index()] & enemy_pawns).popcnt()
+                == 0
+            {
+                let advanced = match color {
+                    Color::White => square.get_rank().to_index() as f32 - 1.0,
+                    Color::Black => 6.0 - square.get_rank().to_index() as f32,
+                };
+                bonus += value * (advanced / 5.0).powi(2);
+            }
         }
+        bonus
     }
 
-    pub fn evaluate(&self, state: Board) -> f32 {
-        if state.status() == BoardStatus::Checkmate {
-            return -39.0;
+    // Doubled and isolated pawn penalties for `color`
+    fn pawn_structure(&self, state: &Board, color: Color) -> f32 {
+        let pawns = state.pieces(Piece::Pawn) & state.color_combined(color);
+        let mut penalty = 0.0;
+        for file in ALL_FILES.iter() {
+            let on_file = (pawns & get_file(*file)).popcnt();
+            if on_file == 0 {
+                continue;
+            }
+            penalty += self.doubled_pawn_penalty * (on_file - 1) as f32;
+            if (pawns & get_adjacent_files(*file)).popcnt() == 0 {
+                penalty += self.isolated_pawn_penalty * on_file as f32;
+            }
+        }
+        penalty
+    }
+
+    // Connected and phalanx pawn bonuses for `color`
+    fn pawn_chains(&self, state: &Board, color: Color) -> f32 {
+        let pawns = state.pieces(Piece::Pawn) & state.color_combined(color);
+        let mut bonus = 0.0;
+        for square in pawns {
+            // Pawns of `color` that an enemy pawn here would attack are the ones defending it
+            if get_pawn_attacks(square, !color, pawns).popcnt() != 0 {
+                bonus += self.connected_pawn_value;
+            }
+            let beside = get_adjacent_files(square.get_file()) & get_rank(square.get_rank());
+            if (beside & pawns).popcnt() != 0 {
+                bonus += self.phalanx_pawn_value;
+            }
+        }
+        bonus
+    }
+
+    // Open and half-open files and seventh rank bonuses for `color`'s rooks
+    fn rook_placement(&self, state: &Board, color: Color) -> f32 {
+        let pawns = state.pieces(Piece::Pawn);
+        let own_pawns = pawns & state.color_combined(color);
+        let seventh = match color {
+            Color::White => Rank::Seventh,
+            Color::Black => Rank::Second,
+        };
+        let mut bonus = 0.0;
+        for square in state.pieces(Piece::Rook) & state.color_combined(color) {
+            let file = get_file(square.get_file());
+            if (file & pawns).popcnt() == 0 {
+                bonus += self.rook_file_values[1];
+            } else if (file & own_pawns).popcnt() == 0 {
+                bonus += self.rook_file_values[0];
+            }
+            if square.get_rank() == seventh {
+                bonus += self.rook_seventh_value;
+            }
+        }
+        bonus
+    }
+
+    // Penalty for `color`'s minor and major pieces that an enemy piece could
+    // win, by capturing with something cheaper or taking them for free
+    fn hanging_pieces(&self, state: &Board, color: Color, phase: f32) -> f32 {
+        let occupied = *state.combined();
+        let own = state.color_combined(color);
+        let enemy = state.color_combined(!color);
+        let pieces = state.pieces(Piece::Knight)
+            | state.pieces(Piece::Bishop)
+            | state.pieces(Piece::Rook)
+            | state.pieces(Piece::Queen);
+        let mut penalty = 0.0;
+        for square in pieces & own {
+            let attackers = Evaluator::attackers(state, square, occupied);
+            let enemy_attackers = attackers & enemy;
+            if enemy_attackers.popcnt() == 0 {
+                continue;
+            }
+            let value = self.tapered_value(state.piece_on(square).unwrap(), phase);
+            let cheapest = enemy_attackers
+                .map(|attacker| match state.piece_on(attacker).unwrap() {
+                    Piece::King => SEE_KING_VALUE,
+                    piece => self.tapered_value(piece, phase),
+                })
+                .fold(f32::INFINITY, f32::min);
+            if cheapest < value || (attackers & own).popcnt() == 0 {
+                penalty += self.hanging_piece_factor * value;
+            }
+        }
+        penalty
+    }
+
+    // Missing pawn shield and enemy attacks around `color`'s king, scaled down
+    // to nothing in the endgame
+    fn king_danger(&self, state: &Board, color: Color, phase: f32) -> f32 {
+        if phase == 0.0 {
+            return 0.0;
+        }
+        let king = state.king_square(color);
+        let pawns = state.pieces(Piece::Pawn) & state.color_combined(color);
+        let shield = self.king_shield_masks[color.to_index()][king.to_index()];
+        let mut missing = 0;
+        for file in ALL_FILES.iter() {
+            let in_front = shield & get_file(*file);
+            if in_front.popcnt() != 0 && (in_front & pawns).popcnt() == 0 {
+                missing += 1;
+            }
+        }
+
+        let zone = self.king_zone_masks[king.to_index()];
+        let occupied = *state.combined();
+        let mut attacks = 0;
+        for square in *state.color_combined(!color) {
+            let attacked = match state.piece_on(square) {
+                Some(Piece::Pawn) => get_pawn_attacks(square, !color, !BitBoard::new(0)),
+                Some(Piece::Knight) => get_knight_moves(square),
+                Some(Piece::Bishop) => get_bishop_moves(square, occupied),
+                Some(Piece::Rook) => get_rook_moves(square, occupied),
+                Some(Piece::Queen) => {
+                    get_bishop_moves(square, occupied) | get_rook_moves(square, occupied)
+                }
+                _ => continue,
+            };
+            attacks += (attacked & zone).popcnt();
+        }
+
+        phase
+            * (self.king_shield_penalty * missing as f32 + self.king_attack_weight * attacks as f32)
+    }
+
+    fn king_ring_value(&self, king: Square) -> f32 {
+        if self.center.contains(&king) {
+            self.king_ring_values[0]
+        } else if self.inner_ring.contains(&king) {
+            self.king_ring_values[1]
+        } else if self.mid_ring.contains(&king) {
+            self.king_ring_values[2]
+        } else if self.outer_ring.contains(&king) {
+            self.king_ring_values[3]
+        } else {
+            0.0
+        }
+    }
+
+    pub fn evaluate(&self, state: &Board) -> f32 {
+        match state.status() {
+            BoardStatus::Checkmate => return MATE_VALUE,
+            BoardStatus::Stalemate => return 0.0,
+            BoardStatus::Ongoing => (),
+        }
+        if insufficient_material(state) {
+            return 0.0;
         }
 
         let mut value = 0.0;
@@ -41,43 +775,62 @@ impl Evaluator {
         let rooks = state.pieces(Piece::Rook);
         let queens = state.pieces(Piece::Queen);
 
-        value -= (black & pawns).popcnt() as f32 * self.piece_value_map[&Piece::Pawn];
-        value -= (black & bishops).popcnt() as f32 * self.piece_value_map[&Piece::Bishop];
-        value -= (black & knights).popcnt() as f32 * self.piece_value_map[&Piece::Knight];
-        value -= (black & rooks).popcnt() as f32 * self.piece_value_map[&Piece::Rook];
-        value -= (black & queens).popcnt() as f32 * self.piece_value_map[&Piece::Queen];
-        value += (white & pawns).popcnt() as f32 * self.piece_value_map[&Piece::Pawn];
-        value += (white & bishops).popcnt() as f32 * self.piece_value_map[&Piece::Bishop];
-        value += (white & knights).popcnt() as f32 * self.piece_value_map[&Piece::Knight];
-        value += (white & rooks).popcnt() as f32 * self.piece_value_map[&Piece::Rook];
-        value += (white & queens).popcnt() as f32 * self.piece_value_map[&Piece::Queen];
+        let phase = Evaluator::game_phase(state);
+        let piece_value = |piece| self.tapered_value(piece, phase);
+        value -= (black & pawns).popcnt() as f32 * piece_value(Piece::Pawn);
+        value -= (black & bishops).popcnt() as f32 * piece_value(Piece::Bishop);
+        value -= (black & knights).popcnt() as f32 * piece_value(Piece::Knight);
+        value -= (black & rooks).popcnt() as f32 * piece_value(Piece::Rook);
+        value -= (black & queens).popcnt() as f32 * piece_value(Piece::Queen);
+        value += (white & pawns).popcnt() as f32 * piece_value(Piece::Pawn);
+        value += (white & bishops).popcnt() as f32 * piece_value(Piece::Bishop);
+        value += (white & knights).popcnt() as f32 * piece_value(Piece::Knight);
+        value += (white & rooks).popcnt() as f32 * piece_value(Piece::Rook);
+        value += (white & queens).popcnt() as f32 * piece_value(Piece::Queen);
+
+        // Trade pieces when ahead, keep them on when behind
+        let pieces = (bishops | knights | rooks | queens).popcnt() as f32;
+        let traded = (14.0 - pieces).max(0.0);
+        if value > 0.0 {
+            value += self.trade_weight * traded;
+        } else if value < 0.0 {
+            value -= self.trade_weight * traded;
+        }
+
+        value += self.passed_pawns(state, Color::White, phase);
+        value -= self.passed_pawns(state, Color::Black, phase);
+        value -= self.pawn_structure(state, Color::White);
+        value += self.pawn_structure(state, Color::Black);
+        value += self.pawn_chains(state, Color::White);
+        value -= self.pawn_chains(state, Color::Black);
+
+        let bishop_pair =
+            self.bishop_pair_values[0] * phase + self.bishop_pair_values[1] * (1.0 - phase);
+        if (white & bishops).popcnt() >= 2 {
+            value += bishop_pair;
+        }
+        if (black & bishops).popcnt() >= 2 {
+            value -= bishop_pair;
+        }
+
+        value -= self.king_danger(state, Color::White, phase);
+        value += self.king_danger(state, Color::Black, phase);
+
+        value += self.rook_placement(state, Color::White);
+        value -= self.rook_placement(state, Color::Black);
+
+        value -= self.hanging_pieces(state, Color::White, phase);
+        value += self.hanging_pieces(state, Color::Black, phase);
 
         // Value for pushing king to outside in endgame
         if black.popcnt() <= 4 {
-            let king = state.king_square(Color::Black);
-            if self.center.contains(&king) {
-                value -= 0.5
-            } else if self.inner_ring.contains(&king) {
-                value -= 0.25
-            } else if self.mid_ring.contains(&king) {
-                value += 0.25
-            } else if self.outer_ring.contains(&king) {
-                value += 0.5
-            }
+            value -= self.king_ring_value(state.king_square(Color::Black));
         }
         if white.popcnt() <= 4 {
-            let king = state.king_square(Color::White);
-            if self.center.contains(&king) {
-                value += 0.5
-            } else if self.inner_ring.contains(&king) {
-                value += 0.25
-            } else if self.mid_ring.contains(&king) {
-                value -= 0.25
-            } else if self.outer_ring.contains(&king) {
-                value -= 0.5
-            }
+            value += self.king_ring_value(state.king_square(Color::White));
         }
 
+        // Terms so far are from White's view, the rest are for the side to move
         if state.side_to_move() == Color::Black {
             value = -value
         }
@@ -92,20 +845,28 @@ impl Evaluator {
         }
 
         // Value for center control
-        for action in MoveGen::new_legal(&state) {
+        let mut mobility = 0;
+        for action in MoveGen::new_legal(state) {
+            mobility += 1;
             if self.center.contains(&action.get_dest()) {
-                value += 0.25
+                value += self.center_control_value
             }
         }
         // Flip board with null move to get opponent's info
-        // Skipped if currently in check
+        // Skipped if currently in check, or if no opponent term is weighted
         if state.checkers().popcnt() == 0 {
-            let opp_state = state.null_move().unwrap();
-            assert_ne!(state, opp_state);
+            if self.center_control_value != 0.0 || self.mobility_weight != 0.0 {
+                // A null move is only refused when in check, which was ruled out above
+                if let Some(opp_state) = state.null_move() {
+                    assert_ne!(*state, opp_state);
 
-            for action in MoveGen::new_legal(&opp_state) {
-                if self.center.contains(&action.get_dest()) {
-                    value -= 0.25
+                    for action in MoveGen::new_legal(&opp_state) {
+                        mobility -= 1;
+                        if self.center.contains(&action.get_dest()) {
+                            value -= self.center_control_value
+                        }
+                    }
+                    value += self.mobility_weight * mobility as f32;
                 }
             }
             let pinned: Vec<_> = state.pinned().collect();
@@ -117,18 +878,131 @@ impl Evaluator {
             }
         } else {
             // Value loss for each checker
-            value -= 0.75 * state.checkers().popcnt() as f32
+            value -= self.checker_penalty * state.checkers().popcnt() as f32
+        }
+
+        value
+    }
+
+    // (side to move value, white relative value) of the same evaluation
+    pub fn evaluate_both(&self, state: &Board) -> (f32, f32) {
+        let value = self.evaluate(state);
+        match state.side_to_move() {
+            Color::White => (value, value),
+            Color::Black => (value, -value),
         }
+    }
 
+    // Evaluate with the hashes of earlier positions, a repeated position is valued
+    // closer to the draw it leads toward, for whichever side is to move
+    pub fn evaluate_with_history(&self, state: &Board, history: &[u64]) -> f32 {
+        self.adjust_for_history(self.evaluate(state), state, history)
+    }
+
+    // Apply the repetition term to an already computed evaluation of `state`
+    pub fn adjust_for_history(&self, value: f32, state: &Board, history: &[u64]) -> f32 {
+        if history.contains(&state.get_hash()) {
+            return value * self.repetition_factor;
+        }
         value
     }
 
-    pub fn priors(&self, state: Board) -> HashMap<ChessMove, f32> {
-        let mut priors = HashMap::new();
+    // Pieces of either color attacking `square` given the `occupied` squares, so
+    // sliders behind pieces already traded off are seen
+    fn attackers(state: &Board, square: Square, occupied: BitBoard) -> BitBoard {
+        let white_pawns = state.pieces(Piece::Pawn) & state.color_combined(Color::White);
+        let black_pawns = state.pieces(Piece::Pawn) & state.color_combined(Color::Black);
+        let diagonal = state.pieces(Piece::Bishop) | state.pieces(Piece::Queen);
+        let straight = state.pieces(Piece::Rook) | state.pieces(Piece::Queen);
+        let attackers = get_pawn_attacks(square, Color::Black, white_pawns)
+            | get_pawn_attacks(square, Color::White, black_pawns)
+            | (get_knight_moves(square) & state.pieces(Piece::Knight))
+            | (get_king_moves(square) & state.pieces(Piece::King))
+            | (get_bishop_moves(square, occupied) & diagonal)
+            | (get_rook_moves(square, occupied) & straight);
+        attackers & occupied
+    }
 
-        let score = |state: Board| {
-            if state.status() == BoardStatus::Checkmate {
-                return -16.0;
+    fn see_value(&self, piece: Piece) -> f32 {
+        match piece {
+            Piece::King => SEE_KING_VALUE,
+            _ => self.piece_value_map[&piece],
+        }
+    }
+
+    // Static exchange evaluation, the material the side to move wins by playing
+    // `action` once both sides keep recapturing on its square with their least
+    // valuable piece, and stop when that would lose material. Pins are ignored
+    pub fn see(&self, state: &Board, action: ChessMove) -> f32 {
+        let source = action.get_source();
+        let target = action.get_dest();
+        let mut on_target = state.piece_on(source).unwrap();
+        let mut occupied = *state.combined() ^ BitBoard::from_square(source);
+        let mut gains = vec![match state.piece_on(target) {
+            Some(piece) => self.see_value(piece),
+            // En passant, the captured pawn stands beside the moving one
+            None if on_target == Piece::Pawn && source.get_file() != target.get_file() => {
+                occupied ^= BitBoard::from_square(Square::make_square(
+                    source.get_rank(),
+                    target.get_file(),
+                ));
+                self.see_value(Piece::Pawn)
+            }
+            None => 0.0,
+        }];
+        if let Some(promotion) = action.get_promotion() {
+            gains[0] += self.see_value(promotion) - self.see_value(Piece::Pawn);
+            on_target = promotion;
+        }
+
+        let mut side = !state.side_to_move();
+        loop {
+            let attackers =
+                Evaluator::attackers(state, target, occupied) & state.color_combined(side);
+            let attacker = [
+                Piece::Pawn,
+                Piece::Knight,
+                Piece::Bishop,
+                Piece::Rook,
+                Piece::Queen,
+                Piece::King,
+            ]
+            .iter()
+            .find_map(|piece| {
+                (attackers & state.pieces(*piece))
+                    .into_iter()
+                    .next()
+                    .map(|square| (*piece, square))
+            });
+            let (piece, square) = match attacker {
+                Some(attacker) => attacker,
+                None => break,
+            };
+            gains.push(self.see_value(on_target) - gains[gains.len() - 1]);
+            occupied ^= BitBoard::from_square(square);
+            on_target = piece;
+            side = !side;
+        }
+
+        // Either side can stop capturing when carrying on would lose material
+        while gains.len() > 1 {
+            let last = gains.pop().unwrap();
+            let previous = gains.last_mut().unwrap();
+            *previous = -(-*previous).max(last);
+        }
+        gains[0]
+    }
+
+    // Prior score of each move, with the factor its normalized prior is boosted by
+    fn move_scores(&self, state: &Board, moves: &[ChessMove]) -> (Vec<f32>, Vec<f32>) {
+        let mut scores = vec![];
+        let mut boosts = vec![];
+
+        let score = |state: &Board| {
+            match state.status() {
+                BoardStatus::Checkmate => return PRIOR_MATE_SCORE,
+                BoardStatus::Stalemate => return 0.0,
+                BoardStatus::Ongoing => (),
             }
             let piece_diff = state.color_combined(Color::White).popcnt() as f32
                 - state.color_combined(Color::Black).popcnt() as f32;
@@ -138,33 +1012,238 @@ impl Evaluator {
             }
         };
 
-        for action in MoveGen::new_legal(&state) {
+        for action in moves.iter().copied() {
             let new_state = state.make_move_new(action);
-            assert_ne!(new_state, state);
-            priors.insert(action, score(new_state) + 0.0000001);
+            assert_ne!(new_state, *state);
+            let mut value = score(&new_state);
+            // Promoting doesn't change the piece count, so credit the promoted piece's worth
+            if let Some(piece) = action.get_promotion() {
+                value -= self.piece_value_map[&piece] - self.piece_value_map[&Piece::Pawn];
+            }
+            let mut boost = 1.0;
+            // The bonus ranks a check above moves of the same piece count, such as
+            // a checking underpromotion against a queen promotion, while the factor
+            // keeps checks standing out once normalizing has flattened the scores.
+            // A bonus of 0 or a factor of 1 leaves one of them to tune on its own
+            if new_state.checkers().popcnt() > 0 {
+                value -= self.check_prior_bonus;
+                boost *= self.check_prior_factor;
+            }
+            // A capture looks like winning a piece by count even when it loses the exchange
+            let source = action.get_source();
+            let en_passant = state.piece_on(source) == Some(Piece::Pawn)
+                && source.get_file() != action.get_dest().get_file();
+            if state.piece_on(action.get_dest()).is_some() || en_passant {
+                if self.see(state, action) < 0.0 {
+                    value += LOSING_CAPTURE_PRIOR_PENALTY;
+                } else {
+                    boost *= self.capture_prior_factor;
+                }
+            }
+            // Both scores are for the opponent, who is to move after the action
+            if self.prior_eval_mix > 0.0 {
+                value = value * (1.0 - self.prior_eval_mix)
+                    + self.evaluate(&new_state) * self.prior_eval_mix;
+            }
+            scores.push(value + 0.0000001);
+            boosts.push(boost);
         }
+        (scores, boosts)
+    }
 
-        if priors.is_empty() {
-            return priors;
+    // Turn scores into probabilities that favour the lowest score
+    fn normalize_scores(scores: &[f32]) -> Vec<f32> {
+        if scores.is_empty() {
+            return vec![];
         }
 
-        let abs_min = priors
-            .values()
+        let abs_min = scores
+            .iter()
             .min_by_key(|v| OrderedFloat(**v))
             .unwrap()
             .abs();
-        let max = (priors.values().max_by_key(|v| OrderedFloat(**v)).unwrap() + abs_min) * 1.25;
-        let mut new_priors = HashMap::new();
-        for (action, value) in priors.iter() {
-            new_priors.insert(*action, max - (value + abs_min));
-        }
+        let max = (scores.iter().max_by_key(|v| OrderedFloat(**v)).unwrap() + abs_min) * 1.25;
+        let shifted: Vec<f32> = scores.iter().map(|v| max - (v + abs_min)).collect();
 
-        let sum: f32 = new_priors.values().sum();
+        let sum: f32 = shifted.iter().sum();
         let norm_factor = 1.0 / (sum + 0.0000001);
-        for (action, value) in new_priors.iter() {
-            priors.insert(*action, value * norm_factor);
+        shifted.iter().map(|v| v * norm_factor).collect()
+    }
+
+    // normalize_scores with each probability multiplied by its boost, renormalized
+    fn boosted_priors(scores: &[f32], boosts: &[f32]) -> Vec<f32> {
+        let boosted: Vec<f32> = Self::normalize_scores(scores)
+            .iter()
+            .zip(boosts)
+            .map(|(prior, boost)| prior * boost)
+            .collect();
+        let norm_factor = 1.0 / (boosted.iter().sum::<f32>() + 0.0000001);
+        boosted.iter().map(|v| v * norm_factor).collect()
+    }
+
+    pub fn priors(&self, state: &Board) -> HashMap<ChessMove, f32> {
+        let moves: Vec<ChessMove> = MoveGen::new_legal(state).collect();
+        self.priors_with_moves(state, &moves)
+    }
+
+    // Priors over already generated legal moves of `state`
+    pub fn priors_with_moves(&self, state: &Board, moves: &[ChessMove]) -> HashMap<ChessMove, f32> {
+        if self.uniform_priors {
+            let prior = 1.0 / moves.len() as f32;
+            return moves.iter().map(|action| (*action, prior)).collect();
         }
+        let (scores, boosts) = self.move_scores(state, moves);
+        moves
+            .iter()
+            .copied()
+            .zip(Self::boosted_priors(&scores, &boosts))
+            .collect()
+    }
+
+    // A draw can be claimed once the position has already appeared twice
+    pub fn can_claim_draw(state: &Board, history: &[u64]) -> bool {
+        let hash = state.get_hash();
+        history.iter().filter(|h| **h == hash).count() >= 2
+    }
+
+    // Priors given the hashes of earlier positions, with the prior of claiming a
+    // draw alongside the moves when one is available
+    pub fn priors_with_history(
+        &self,
+        state: &Board,
+        history: &[u64],
+    ) -> (HashMap<ChessMove, f32>, Option<f32>) {
+        let claim = match self.draw_claim_value {
+            Some(value) if Self::can_claim_draw(state, history) => value,
+            _ => return (self.priors(state), None),
+        };
+
+        let moves: Vec<ChessMove> = MoveGen::new_legal(state).collect();
+        let (mut scores, mut boosts) = self.move_scores(state, &moves);
+        scores.push(claim);
+        boosts.push(1.0);
+        let mut priors = Self::boosted_priors(&scores, &boosts);
+        let claim_prior = priors.pop();
+        (moves.into_iter().zip(priors).collect(), claim_prior)
+    }
+}
+
+// What the tree needs from an evaluation, so experimental evaluators can be
+// searched in place of Evaluator
+pub trait Evaluate: Sync {
+    // Value of the position for the side to move, in pawns, positive when it
+    // is ahead. A checkmated side to move has lost, so mates are MATE_VALUE,
+    // and the tree negates each child's value to back it up to its parent
+    fn evaluate(&self, state: &Board) -> f32;
+
+    // Probability of each legal move being the one to search
+    fn priors(&self, state: &Board) -> HashMap<ChessMove, f32>;
+
+    // `priors` for when the legal moves are already known
+    fn priors_with_moves(&self, state: &Board, _moves: &[ChessMove]) -> HashMap<ChessMove, f32> {
+        self.priors(state)
+    }
+
+    // Changes whenever the evaluation does, so cached results can be dropped.
+    // Evaluators that never change can keep the default
+    fn version(&self) -> u64 {
+        0
+    }
+
+    // Value of a position that has already occurred in the game, unchanged by default
+    fn adjust_for_history(&self, value: f32, _state: &Board, _history: &[u64]) -> f32 {
+        value
+    }
+
+    // Prior of claiming a draw in a position reached after `history`, None when
+    // no claim is available or the evaluator never claims
+    fn claim_prior(&self, _state: &Board, _history: &[u64]) -> Option<f32> {
+        None
+    }
+
+    // Evaluation after resolving captures, and check evasions, up to `depth`
+    // plies deep, so a leaf isn't valued just before a piece is lost. At most
+    // `max_nodes` positions are expanded, later ones are evaluated statically
+    fn quiescence(&self, state: &Board, depth: usize, max_nodes: usize) -> f32 {
+        let mut budget = max_nodes;
+        quiesce(
+            self,
+            state,
+            f32::NEG_INFINITY,
+            f32::INFINITY,
+            depth,
+            &mut budget,
+        )
+    }
+}
+
+fn quiesce<E: Evaluate + ?Sized>(
+    evaluator: &E,
+    state: &Board,
+    mut alpha: f32,
+    beta: f32,
+    depth: usize,
+    budget: &mut usize,
+) -> f32 {
+    let in_check = state.checkers().popcnt() != 0;
+    if depth == 0 || *budget == 0 || state.status() != BoardStatus::Ongoing {
+        return evaluator.evaluate(state);
+    }
+    *budget -= 1;
+
+    // Out of check the side to move can decline every capture
+    let mut best = f32::NEG_INFINITY;
+    let mut moves = MoveGen::new_legal(state);
+    if !in_check {
+        best = evaluator.evaluate(state);
+        if best >= beta {
+            return best;
+        }
+        alpha = alpha.max(best);
+        moves.set_iterator_mask(*state.color_combined(!state.side_to_move()));
+    }
+
+    for action in moves {
+        let value = -quiesce(
+            evaluator,
+            &state.make_move_new(action),
+            -beta,
+            -alpha,
+            depth - 1,
+            budget,
+        );
+        best = best.max(value);
+        alpha = alpha.max(value);
+        // Out of budget, settle for the best line found so far
+        if alpha >= beta || *budget == 0 {
+            break;
+        }
+    }
+    best
+}
+
+impl Evaluate for Evaluator {
+    fn evaluate(&self, state: &Board) -> f32 {
+        Evaluator::evaluate(self, state)
+    }
+
+    fn priors(&self, state: &Board) -> HashMap<ChessMove, f32> {
+        Evaluator::priors(self, state)
+    }
+
+    fn priors_with_moves(&self, state: &Board, moves: &[ChessMove]) -> HashMap<ChessMove, f32> {
+        Evaluator::priors_with_moves(self, state, moves)
+    }
+
+    fn version(&self) -> u64 {
+        Evaluator::version(self)
+    }
+
+    fn adjust_for_history(&self, value: f32, state: &Board, history: &[u64]) -> f32 {
+        Evaluator::adjust_for_history(self, value, state, history)
+    }
 
-        priors
+    fn claim_prior(&self, state: &Board, history: &[u64]) -> Option<f32> {
+        self.priors_with_history(state, history).1
     }
 }
@@ -1,23 +1,36 @@
 #![allow(unused_imports)]
 use crate::{
+    book::Book,
     eval::Evaluator,
-    mcts::{Limit, Tree},
+    mcts::{
+        resets_halfmove_clock, select_move, Limit, Outcome, Tree, DEFAULT_CACHE_CAPACITY,
+        DEFAULT_EARLY_EXIT_RATIO,
+    },
 };
-use chess::{Board, ChessMove, MoveGen};
+use chess::{Action, Board, ChessMove, File, GameResult, MoveGen, Piece, Square};
 use ordered_float::OrderedFloat;
-use pyo3::prelude::*;
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+};
+use rand::thread_rng;
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    f32::consts::SQRT_2,
     str::FromStr,
-    sync::{mpsc, Arc, Mutex},
-    thread,
+    sync::{Arc, Mutex},
     time::Instant,
 };
 
-mod eval;
-mod mcts;
+pub mod book;
+pub mod eval;
+pub mod mcts;
+pub mod perft;
+pub mod polyglot;
+pub mod selfplay;
 
-fn uci(action: &ChessMove) -> String {
+pub fn uci(action: &ChessMove) -> String {
     let squares = vec![
         "A1", "B1", "C1", "D1", "E1", "F1", "G1", "H1", "A2", "B2", "C2", "D2", "E2", "F2", "G2",
         "H2", "A3", "B3", "C3", "D3", "E3", "F3", "G3", "H3", "A4", "B4", "C4", "D4", "E4", "F4",
@@ -27,72 +40,207 @@ fn uci(action: &ChessMove) -> String {
     ];
     let src = action.get_source().to_index();
     let dst = action.get_dest().to_index();
+    let promotion = match action.get_promotion() {
+        Some(piece) => format!("{}", piece),
+        None => String::new(),
+    };
     format!(
-        "{}{}",
+        "{}{}{}",
         squares[src].to_lowercase(),
-        squares[dst].to_lowercase()
+        squares[dst].to_lowercase(),
+        promotion
     )
 }
 
-#[pyfunction]
-fn search_tree(fen: String, time: f32, temperature: f32, processes: usize) -> String {
-    let start = Instant::now();
-
-    let mut handles = vec![];
-    let mut move_dict = HashMap::new();
+// Castling as a king move of two squares, or as the king taking its own rook
+// the way Chess960 GUIs expect it
+pub fn uci_move(board: &Board, action: &ChessMove, chess960: bool) -> String {
+    let source = action.get_source();
+    let dest = action.get_dest();
+    let castles = board.piece_on(source) == Some(Piece::King)
+        && (source.get_file().to_index() as i32 - dest.get_file().to_index() as i32).abs() == 2;
+    if !chess960 || !castles {
+        return uci(action);
+    }
+    let rook_file = if dest.get_file() > source.get_file() {
+        File::H
+    } else {
+        File::A
+    };
+    uci(&ChessMove::new(
+        source,
+        Square::make_square(source.get_rank(), rook_file),
+        None,
+    ))
+}
 
-    for action in MoveGen::new_legal(&Board::from_str(&fen).unwrap()) {
-        move_dict.insert(action, 0);
+// Turn a king-takes-rook castling move back into the two square king move
+pub fn from_king_takes_rook(board: &Board, action: ChessMove) -> ChessMove {
+    let source = action.get_source();
+    let dest = action.get_dest();
+    if board.piece_on(source) != Some(Piece::King)
+        || board.piece_on(dest) != Some(Piece::Rook)
+        || board.color_on(dest) != board.color_on(source)
+    {
+        return action;
+    }
+    let king_dest = match dest.get_file() {
+        File::H => source.right().and_then(|s| s.right()),
+        File::A => source.left().and_then(|s| s.left()),
+        _ => None,
+    };
+    match king_dest {
+        Some(king_dest) => ChessMove::new(source, king_dest, None),
+        None => action,
     }
+}
 
-    let fen_mtx = Arc::new(Mutex::new(fen));
-    let time_mtx = Arc::new(Mutex::new(time));
-    let temperature_mtx = Arc::new(Mutex::new(temperature));
+pub fn parse_board(fen: &str) -> Result<Board, String> {
+    Board::from_str(fen).map_err(|e| format!("invalid FEN {:?}: {}", fen, e))
+}
 
-    let (tx, rx) = mpsc::channel();
-    let tx_mtx = Arc::new(Mutex::new(tx));
+fn load_book(path: &str) -> PyResult<Book> {
+    Book::from_polyglot(path).map_err(|e| PyIOError::new_err(format!("{}: {}", path, e)))
+}
 
-    for _ in 0..processes {
-        let t_fen = Arc::clone(&fen_mtx);
-        let t_time = Arc::clone(&time_mtx);
-        let t_temperature = Arc::clone(&temperature_mtx);
-        let t_tx = Arc::clone(&tx_mtx);
+// Books opened by search_tree by path, so each file is only read once
+static BOOKS: Mutex<BTreeMap<String, Arc<Book>>> = Mutex::new(BTreeMap::new());
 
-        let handle = thread::spawn(move || {
-            let board = Board::from_str(&t_fen.lock().unwrap()).unwrap();
+fn cached_book(path: &str) -> PyResult<Arc<Book>> {
+    let mut books = BOOKS.lock().unwrap();
+    if let Some(book) = books.get(path) {
+        return Ok(Arc::clone(book));
+    }
+    let book = Arc::new(load_book(path)?);
+    books.insert(path.to_string(), Arc::clone(&book));
+    Ok(book)
+}
 
-            let evaluator = Evaluator::new();
-            let mut tree = Tree::new(evaluator, *t_temperature.lock().unwrap(), 0.3);
-            let limit = Limit::new(Some(*t_time.lock().unwrap()), Some(0.0));
+// Parse UCI `moves` played in turn from `board`, failing on the first illegal one
+fn parse_moves(mut board: Board, moves: &[String]) -> PyResult<Vec<ChessMove>> {
+    let mut actions = vec![];
+    for text in moves.iter() {
+        let action = ChessMove::from_str(text)
+            .ok()
+            .filter(|action| board.legal(*action))
+            .ok_or_else(|| PyValueError::new_err(format!("illegal move {}", text)))?;
+        actions.push(action);
+        board = board.make_move_new(action);
+    }
+    Ok(actions)
+}
 
-            let results = tree.search(board, limit);
-            for result in results {
-                t_tx.lock().unwrap().send(result).unwrap();
-            }
-        });
-        handles.push(handle);
+// Play `moves` from `board`, returning the position reached and the hashes of
+// the positions before it
+fn play_moves(mut board: Board, moves: &[ChessMove]) -> (Board, Vec<u64>) {
+    let mut history = vec![];
+    for action in moves.iter() {
+        history.push(board.get_hash());
+        board = board.make_move_new(*action);
     }
+    (board, history)
+}
 
-    drop(tx_mtx);
-    for (action, visits) in rx {
-        *move_dict.get_mut(&action).unwrap() += visits as usize;
+// Halfmove clock after playing `moves` from `board`, starting from the one
+// `fen` gives for it
+pub fn halfmove_clock(fen: &str, mut board: Board, moves: &[ChessMove]) -> u32 {
+    let mut clock = fen
+        .split_whitespace()
+        .nth(4)
+        .and_then(|clock| clock.parse().ok())
+        .unwrap_or(0);
+    for action in moves.iter() {
+        clock = if resets_halfmove_clock(&board, *action) {
+            0
+        } else {
+            clock + 1
+        };
+        board = board.make_move_new(*action);
     }
+    clock
+}
 
-    let mut results = vec![];
-    for item in move_dict.iter() {
-        results.push(item);
+// Weights saved with `Evaluator::save`, or the default evaluator
+fn load_weights(path: Option<String>) -> PyResult<Evaluator> {
+    match path {
+        Some(path) => {
+            Evaluator::load(&path).map_err(|e| PyIOError::new_err(format!("{}: {}", path, e)))
+        }
+        None => Ok(Evaluator::new()),
     }
+}
 
-    for handle in handles {
-        handle.join().unwrap();
+// Search the position reached by playing UCI `moves` from `fen`. Pass the
+// game's starting FEN and every move since, so earlier positions count toward
+// repetitions. The halfmove clock of the FEN counts toward fifty-move draws.
+// `processes` threads search one shared tree, 0 for one per available core.
+// The search stops after `time` seconds or `nodes` nodes, 0 or None disables a
+// limit, and with neither set it gets the default budget of `Limit::new`.
+// A move found in the `book` is returned unsearched, with `book` set.
+// `weights` are evaluator weights saved with `Evaluator::save`.
+// `temperature` is the exploration constant and `noise` the Dirichlet alpha.
+// The search ends early once one move holds `early_exit_ratio` of the visits.
+// A `move_temperature` above 0 samples the move from the visits.
+// A positive `contempt` avoids draws, a negative one seeks them.
+#[pyfunction(
+    book = "None",
+    weights = "None",
+    noise = "0.3",
+    early_exit_ratio = "DEFAULT_EARLY_EXIT_RATIO",
+    move_temperature = "0.0",
+    contempt = "0.0",
+    moves = "None",
+    nodes = "None"
+)]
+#[allow(clippy::too_many_arguments)]
+fn search_tree(
+    fen: String,
+    time: f32,
+    temperature: f32,
+    processes: usize,
+    book: Option<String>,
+    weights: Option<String>,
+    noise: f32,
+    early_exit_ratio: f32,
+    move_temperature: f32,
+    contempt: f32,
+    moves: Option<Vec<String>>,
+    nodes: Option<f32>,
+) -> PyResult<SearchResult> {
+    let start = parse_board(&fen).map_err(PyValueError::new_err)?;
+    let moves = parse_moves(start, &moves.unwrap_or_default())?;
+    let (board, history) = play_moves(start, &moves);
+    let clock = halfmove_clock(&fen, start, &moves);
+    if let Some(path) = book {
+        if let Some(action) = cached_book(&path)?.probe(&board) {
+            return Ok(SearchResult {
+                best_move: uci(&action),
+                visits: vec![],
+                fen: board.to_string(),
+                threads: vec![],
+                book: true,
+            });
+        }
+    }
+
+    let evaluator = load_weights(weights)?;
+
+    let start = Instant::now();
+    let mut tree = Tree::new(evaluator, temperature, noise)
+        .with_early_exit_ratio(early_exit_ratio)
+        .with_threads(processes)
+        .with_contempt(contempt);
+    let limit = Limit::new(Some(time), nodes, None);
+    let mut results = tree.search_in_game(board, &history, clock, limit);
+    results.sort_by_key(|(_, visits)| Reverse(OrderedFloat(*visits)));
+    if results.is_empty() {
+        return Err(PyValueError::new_err("no legal moves in this position"));
     }
 
-    results.sort_by_key(|x| x.1);
-    results.reverse();
     let mut fmt_results = vec![];
-    let mut nodes = 0;
+    let mut nodes = 0.0;
     for (i, (action, value)) in results.iter().enumerate() {
-        nodes += **value;
+        nodes += *value;
         if i < 5 {
             fmt_results.push(format!("{} {:.0}", uci(action), value));
         }
@@ -101,17 +249,275 @@ fn search_tree(fen: String, time: f32, temperature: f32, processes: usize) -> St
     println!(
         "{} | {:.0} nodes/s ({:.2}s | {:.0} nodes)",
         fmt_results.join(" | "),
-        nodes as f32 / run_time,
+        nodes / run_time,
         run_time,
         nodes
     );
 
-    uci(&results[0].0)
+    let action = select_move(&results, move_temperature, &mut thread_rng()).unwrap();
+    Ok(SearchResult {
+        best_move: uci(&action),
+        visits: results
+            .iter()
+            .map(|(action, visits)| (uci(action), *visits))
+            .collect(),
+        fen: board.to_string(),
+        threads: tree
+            .thread_stats()
+            .iter()
+            .map(|(action, nodes)| (action.as_ref().map(uci), *nodes))
+            .collect(),
+        book: false,
+    })
+}
+
+// Root moves of a search on `processes` threads as (move, visits, expected
+// value) tuples, most visited first. Values are in pawns for the side to move
+#[pyfunction(noise = "0.3", early_exit_ratio = "DEFAULT_EARLY_EXIT_RATIO")]
+fn analyze(
+    fen: String,
+    time: f32,
+    temperature: f32,
+    processes: usize,
+    noise: f32,
+    early_exit_ratio: f32,
+) -> PyResult<Vec<(String, usize, f32)>> {
+    let board = parse_board(&fen).map_err(PyValueError::new_err)?;
+    let mut tree = Tree::new(Evaluator::new(), temperature, noise)
+        .with_early_exit_ratio(early_exit_ratio)
+        .with_threads(processes);
+    let limit = Limit::new(Some(time), Some(0.0), None);
+    let mut results = tree.search_in_game(board, &[], halfmove_clock(&fen, board, &[]), limit);
+    results.sort_by_key(|(_, visits)| Reverse(OrderedFloat(*visits)));
+    Ok(results
+        .iter()
+        .map(|(action, visits)| {
+            let value = tree.expected_value(*action).unwrap_or(0.0);
+            (uci(action), *visits as usize, value)
+        })
+        .collect())
+}
+
+// Play a game from the start position between two sets of saved weights, None
+// for the default evaluator. Returns the result ("1-0", "0-1" or "1/2-1/2", games
+// reaching `move_limit` moves are adjudicated) and the moves played in UCI
+#[pyfunction]
+fn play_game(
+    white_weights: Option<String>,
+    black_weights: Option<String>,
+    move_limit: usize,
+    time_per_move: f32,
+) -> PyResult<(String, Vec<String>)> {
+    let white = Arc::new(load_weights(white_weights)?);
+    let black = Arc::new(load_weights(black_weights)?);
+    let game = selfplay::play_self_game(
+        white,
+        black,
+        Board::default(),
+        time_per_move,
+        0,
+        0.0,
+        move_limit * 2,
+    );
+
+    let result = match game.result() {
+        Some(GameResult::WhiteCheckmates) | Some(GameResult::BlackResigns) => "1-0",
+        Some(GameResult::BlackCheckmates) | Some(GameResult::WhiteResigns) => "0-1",
+        Some(_) => "1/2-1/2",
+        None => "*",
+    };
+    let moves = game
+        .actions()
+        .iter()
+        .filter_map(|action| match action {
+            Action::MakeMove(action) => Some(uci(action)),
+            _ => None,
+        })
+        .collect();
+    Ok((result.to_string(), moves))
+}
+
+// Leaf count of the move tree, for checking FEN handling and move generation
+#[pyfunction]
+fn perft(fen: String, depth: usize) -> PyResult<u64> {
+    let board = parse_board(&fen).map_err(PyValueError::new_err)?;
+    Ok(perft::perft(&board, depth))
+}
+
+// Static evaluation as (side to move value, white relative value)
+#[pyfunction]
+fn evaluate_both(fen: String) -> PyResult<(f32, f32)> {
+    let board = parse_board(&fen).map_err(PyValueError::new_err)?;
+    Ok(Evaluator::new().evaluate_both(&board))
+}
+
+// Material value of each piece type at the phase of the position
+#[pyfunction]
+fn tapered_piece_values(fen: String) -> PyResult<HashMap<String, f32>> {
+    let board = parse_board(&fen).map_err(PyValueError::new_err)?;
+    Ok(Evaluator::new()
+        .tapered_piece_values(&board)
+        .into_iter()
+        .map(|(piece, value)| (format!("{:?}", piece).to_lowercase(), value))
+        .collect())
+}
+
+// Result of a search, with root moves ordered by visits
+#[pyclass]
+struct SearchResult {
+    #[pyo3(get)]
+    best_move: String,
+    #[pyo3(get)]
+    visits: Vec<(String, f32)>,
+    #[pyo3(get)]
+    fen: String,
+    // Root move each worker thread evaluated the most leaves under and how many
+    // leaves it evaluated, empty when the move didn't come from a search
+    #[pyo3(get)]
+    threads: Vec<(Option<String>, f32)>,
+    // Whether `best_move` came from the opening book rather than a search
+    #[pyo3(get)]
+    book: bool,
+}
+
+// Search the position reached by playing `moves` from the standard start,
+// earlier positions of the game count toward repetitions
+#[pyfunction(noise = "0.3")]
+fn search_moves(moves: Vec<String>, time: f32, noise: f32) -> PyResult<SearchResult> {
+    let moves = parse_moves(Board::default(), &moves)?;
+    let (board, history) = play_moves(Board::default(), &moves);
+    let clock = halfmove_clock("", Board::default(), &moves);
+
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, noise);
+    let limit = Limit::new(Some(time), None, None);
+    let mut results = tree.search_in_game(board, &history, clock, limit);
+    if results.is_empty() {
+        return Err(PyValueError::new_err("no legal moves in this position"));
+    }
+    results.sort_by_key(|(_, visits)| Reverse(OrderedFloat(*visits)));
+
+    Ok(SearchResult {
+        best_move: uci(&results[0].0),
+        visits: results
+            .iter()
+            .map(|(action, visits)| (uci(action), *visits))
+            .collect(),
+        fen: board.to_string(),
+        threads: vec![],
+        book: false,
+    })
+}
+
+// Single threaded search that keeps its tree (and RNG) between calls, and
+// carries the statistics below the moves played on to the next search
+#[pyclass(unsendable)]
+struct Searcher {
+    tree: Tree,
+    book: Option<Book>,
+}
+
+#[pymethods]
+impl Searcher {
+    // Evaluations are cached for the whole game either way, `reuse_tree` also
+    // keeps the tree below the moves played
+    #[new]
+    #[args(reuse_tree = "true")]
+    fn new(temperature: f32, noise: f32, reuse_tree: bool) -> Self {
+        Searcher {
+            tree: Tree::new(Evaluator::new(), temperature, noise)
+                .with_eval_cache(DEFAULT_CACHE_CAPACITY)
+                .with_tree_reuse(reuse_tree),
+            book: None,
+        }
+    }
+
+    // Consult a Polyglot book before searching, see `Book`
+    fn load_book(&mut self, path: String) -> PyResult<usize> {
+        let book = load_book(&path)?;
+        let positions = book.len();
+        self.book = Some(book);
+        Ok(positions)
+    }
+
+    // `moves` are played from `fen` first and count toward repetitions, see `search_tree`.
+    // The tree of the last search is kept when it is on the way to this position
+    #[args(moves = "None")]
+    fn search(&mut self, fen: String, time: f32, moves: Option<Vec<String>>) -> PyResult<String> {
+        let start = parse_board(&fen).map_err(PyValueError::new_err)?;
+        let moves = parse_moves(start, &moves.unwrap_or_default())?;
+        self.tree.advance_along(start, &moves);
+        let (board, history) = play_moves(start, &moves);
+        if let Some(action) = self.book.as_ref().and_then(|book| book.probe(&board)) {
+            return Ok(uci(&action));
+        }
+        let clock = halfmove_clock(&fen, start, &moves);
+        let limit = Limit::new(Some(time), None, None);
+        let results = self.tree.search_in_game(board, &history, clock, limit);
+        let best = results
+            .iter()
+            .max_by_key(|(_, visits)| OrderedFloat(*visits))
+            .ok_or_else(|| PyValueError::new_err("no legal moves in this position"))?;
+        Ok(uci(&best.0))
+    }
+
+    // Main line of the last search as (uci moves, terminal, depth), terminal is
+    // "checkmate", "stalemate", "draw" or None if the line ends at a leaf
+    fn principal_variation(&self) -> (Vec<String>, Option<String>, usize) {
+        let pv = self.tree.principal_variation();
+        let terminal = pv.terminal.map(|outcome| {
+            match outcome {
+                Outcome::Checkmate => "checkmate",
+                Outcome::Stalemate => "stalemate",
+                Outcome::Draw => "draw",
+            }
+            .to_string()
+        });
+        (pv.moves.iter().map(uci).collect(), terminal, pv.depth)
+    }
+
+    // Explored moves of the last search losing more than `margin_cp` to the best
+    fn inferior_moves(&self, margin_cp: i32) -> Vec<(String, i32)> {
+        self.tree
+            .inferior_moves(margin_cp)
+            .iter()
+            .map(|(action, loss)| (uci(action), *loss))
+            .collect()
+    }
+
+    // Visit share of every legal root move in the last search, as training targets
+    fn policy_target(&self) -> Vec<(String, f32)> {
+        self.tree
+            .policy_target()
+            .iter()
+            .map(|(action, probability)| (uci(action), *probability))
+            .collect()
+    }
+
+    // Drop evaluations cached from the previous game
+    fn new_game(&mut self) {
+        self.tree.clear_cache();
+    }
+
+    fn cache_hit_rate(&self) -> f32 {
+        self.tree.cache_hit_rate()
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.tree.reseed(seed);
+    }
 }
 
 #[pymodule]
 #[allow(unused_variables)]
 fn mcts_rust(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(search_tree, m)?)?;
+    m.add_function(wrap_pyfunction!(analyze, m)?)?;
+    m.add_function(wrap_pyfunction!(perft, m)?)?;
+    m.add_function(wrap_pyfunction!(play_game, m)?)?;
+    m.add_function(wrap_pyfunction!(search_moves, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate_both, m)?)?;
+    m.add_function(wrap_pyfunction!(tapered_piece_values, m)?)?;
+    m.add_class::<SearchResult>()?;
+    m.add_class::<Searcher>()?;
     Ok(())
 }
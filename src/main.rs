@@ -0,0 +1,464 @@
+use chess::{Board, ChessMove, Color, MoveGen};
+use mcts_rust::{
+    eval::Evaluator,
+    from_king_takes_rook, halfmove_clock,
+    mcts::{Limit, SearchInfo, Tree, DEFAULT_CACHE_CAPACITY},
+    perft::perft,
+    uci_move,
+};
+use ordered_float::OrderedFloat;
+use std::{
+    cell::Cell,
+    env,
+    io::{self, BufRead, Write},
+    rc::Rc,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const ENGINE_NAME: &str = "Botfjord";
+const ENGINE_AUTHOR: &str = "Jonathan Breidfjord";
+// Used when `go` gives no usable limit, e.g. a bare `go`
+const DEFAULT_MOVE_TIME: f32 = 5.0;
+// Share of the remaining clock spent on a single move
+const CLOCK_DIVISOR: f32 = 30.0;
+const BENCH_NODES: f32 = 20000.0;
+const BENCH_SEED: u64 = 0;
+const BENCH_POSITIONS: [&str; 4] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r1bq1rk1/pp2ppbp/2np1np1/8/3NP3/2N1BP2/PPPQ2PP/R3KB1R w KQ - 0 1",
+];
+
+// Parse `position [startpos | fen <fen>] [moves ...]` into the starting
+// position, the moves played from it and the halfmove clock they lead to
+fn parse_position(args: &[&str]) -> Option<(Board, Vec<ChessMove>, u32)> {
+    let (fen, rest) = match args.first() {
+        Some(&"startpos") => (String::new(), &args[1..]),
+        Some(&"fen") => {
+            let end = args
+                .iter()
+                .position(|a| *a == "moves")
+                .unwrap_or(args.len());
+            (args[1..end].join(" "), &args[end..])
+        }
+        _ => return None,
+    };
+    let start = if fen.is_empty() {
+        Board::default()
+    } else {
+        Board::from_str(&fen).ok()?
+    };
+
+    let mut board = start;
+    let mut moves = vec![];
+    if rest.first() == Some(&"moves") {
+        for text in &rest[1..] {
+            let action = from_king_takes_rook(&board, ChessMove::from_str(text).ok()?);
+            if !board.legal(action) {
+                return None;
+            }
+            moves.push(action);
+            board = board.make_move_new(action);
+        }
+    }
+    let clock = halfmove_clock(&fen, start, &moves);
+    Some((start, moves, clock))
+}
+
+// Map the `go` arguments onto a search limit
+fn parse_go(args: &[&str], side: Color) -> Limit {
+    if args.contains(&"infinite") {
+        return Limit::infinite();
+    }
+    let value = |name: &str| {
+        args.iter()
+            .position(|a| *a == name)
+            .and_then(|i| args.get(i + 1))
+            .and_then(|v| v.parse::<f32>().ok())
+    };
+
+    let nodes = value("nodes");
+    let depth = value("depth");
+    let (clock, increment) = match side {
+        Color::White => (value("wtime"), value("winc")),
+        Color::Black => (value("btime"), value("binc")),
+    };
+    let time = match (value("movetime"), clock) {
+        (Some(movetime), _) => Some(movetime / 1000.0),
+        (None, Some(clock)) => {
+            Some((clock / CLOCK_DIVISOR + increment.unwrap_or(0.0)).min(clock * 0.8) / 1000.0)
+        }
+        (None, None) if nodes.is_none() && depth.is_none() => Some(DEFAULT_MOVE_TIME),
+        (None, None) => None,
+    };
+    Limit::new(time, nodes, depth)
+}
+
+// Shared with the info callback, which runs inside the search
+struct Session {
+    board: Cell<Board>,
+    chess960: Cell<bool>,
+}
+
+impl Session {
+    fn format(&self, board: &Board, action: &ChessMove) -> String {
+        uci_move(board, action, self.chess960.get())
+    }
+
+    // Moves of a line played out from the current position
+    fn format_line(&self, moves: &[ChessMove]) -> String {
+        let mut board = self.board.get();
+        let mut line = vec![];
+        for action in moves {
+            line.push(self.format(&board, action));
+            board = board.make_move_new(*action);
+        }
+        line.join(" ")
+    }
+}
+
+// Search the session's position, `history` holding the hashes of the game
+// positions before it so repetitions are seen, and `clock` its halfmove clock
+fn go(
+    tree: &mut Tree,
+    session: &Session,
+    history: &[u64],
+    clock: u32,
+    limit: Limit,
+    infinite: bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let board = session.board.get();
+    let results = tree.search_in_game(board, history, clock, limit);
+    // bestmove may only follow an infinite search once the GUI has sent stop,
+    // the search can end sooner with a single legal move
+    if infinite {
+        let stop = tree.stop_handle();
+        while !stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    let best = match results
+        .iter()
+        .max_by_key(|(_, visits)| OrderedFloat(*visits))
+    {
+        Some((action, _)) => *action,
+        None => {
+            // No legal moves, the GUI should not have asked
+            writeln!(out, "bestmove 0000")?;
+            return out.flush();
+        }
+    };
+
+    // Progress lines come from the info callback, finish with the full main line
+    let pv = tree.principal_variation();
+    let line = if pv.moves.is_empty() {
+        vec![best]
+    } else {
+        pv.moves
+    };
+    writeln!(
+        out,
+        "info depth {} pv {}",
+        pv.depth.max(1),
+        session.format_line(&line)
+    )?;
+    writeln!(out, "bestmove {}", session.format(&board, &best))?;
+    out.flush()
+}
+
+fn print_info(session: &Session, info: SearchInfo) {
+    let mut line = format!(
+        "info depth {} nodes {} time {} nps {:.0} score {}",
+        info.depth,
+        info.nodes,
+        (info.elapsed * 1000.0) as u64,
+        info.nps,
+        info.score
+    );
+    if let Some(action) = info.best_move {
+        line.push_str(&format!(" pv {}", session.format_line(&[action])));
+    }
+    println!("{}", line);
+}
+
+// Forward stdin lines from a separate thread so `stop` reaches a search in
+// progress. The flag is updated here, in command order, so a `stop` sent
+// right after `go` is never cleared by that `go`
+fn spawn_reader(stop: Arc<AtomicBool>) -> mpsc::Receiver<io::Result<String>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            if let Ok(line) = &line {
+                match line.split_whitespace().next() {
+                    Some("go") => stop.store(false, Ordering::Relaxed),
+                    Some("stop") | Some("quit") => stop.store(true, Ordering::Relaxed),
+                    _ => {}
+                }
+            }
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+        // Closed input ends the session like `quit`
+        stop.store(true, Ordering::Relaxed);
+    });
+    rx
+}
+
+// Work for the search thread, which owns the tree so that commands are still
+// read and answered while it searches
+enum Job {
+    NewGame,
+    // Starting position, the moves played from it and the halfmove clock after them
+    Position(Board, Vec<ChessMove>, u32),
+    Go {
+        limit: Limit,
+        infinite: bool,
+        chess960: bool,
+    },
+}
+
+// Run jobs in the order the commands came in, after sending back the stop
+// handle of the tree
+fn search_thread(
+    handle: mpsc::Sender<Arc<AtomicBool>>,
+    jobs: mpsc::Receiver<Job>,
+) -> io::Result<()> {
+    let mut out = io::stdout();
+    let session = Rc::new(Session {
+        board: Cell::new(Board::default()),
+        chess960: Cell::new(false),
+    });
+    let info_session = Rc::clone(&session);
+    let mut tree = Tree::new(Evaluator::new(), std::f32::consts::SQRT_2, 0.3)
+        .with_eval_cache(DEFAULT_CACHE_CAPACITY)
+        .with_tree_reuse(true)
+        .with_on_info(Box::new(move |info| print_info(&info_session, info)), 0.5);
+    if handle.send(tree.stop_handle()).is_err() {
+        return Ok(());
+    }
+    // Hashes of the positions of the game before the current one
+    let mut history = vec![];
+    let mut clock = 0;
+
+    for job in jobs {
+        match job {
+            Job::NewGame => {
+                session.board.set(Board::default());
+                history.clear();
+                clock = 0;
+                tree.clear_cache();
+            }
+            // The tree of the last search carries over along the moves played since
+            Job::Position(start, moves, position_clock) => {
+                clock = position_clock;
+                tree.advance_along(start, &moves);
+                let mut board = start;
+                history.clear();
+                for action in moves.iter() {
+                    history.push(board.get_hash());
+                    board = board.make_move_new(*action);
+                }
+                session.board.set(board);
+            }
+            Job::Go {
+                limit,
+                infinite,
+                chess960,
+            } => {
+                session.chess960.set(chess960);
+                go(
+                    &mut tree, &session, &history, clock, limit, infinite, &mut out,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn uci_loop() -> io::Result<()> {
+    let mut out = io::stdout();
+    let (handle_tx, handle_rx) = mpsc::channel();
+    let (jobs, job_rx) = mpsc::channel();
+    let worker = thread::spawn(move || search_thread(handle_tx, job_rx));
+    let stop = handle_rx
+        .recv()
+        .map_err(|_| io::Error::other("search thread failed"))?;
+
+    let mut board = Board::default();
+    let mut chess960 = false;
+    for line in spawn_reader(stop) {
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let job = match tokens.first() {
+            Some(&"uci") => {
+                writeln!(out, "id name {}", ENGINE_NAME)?;
+                writeln!(out, "id author {}", ENGINE_AUTHOR)?;
+                writeln!(out, "option name UCI_Chess960 type check default false")?;
+                writeln!(out, "uciok")?;
+                None
+            }
+            Some(&"isready") => {
+                writeln!(out, "readyok")?;
+                None
+            }
+            // setoption name UCI_Chess960 value true
+            Some(&"setoption") if tokens.get(2) == Some(&"UCI_Chess960") => {
+                chess960 = tokens.get(4) == Some(&"true");
+                None
+            }
+            Some(&"ucinewgame") => {
+                board = Board::default();
+                Some(Job::NewGame)
+            }
+            Some(&"position") => match parse_position(&tokens[1..]) {
+                Some((start, moves, clock)) => {
+                    board = moves
+                        .iter()
+                        .fold(start, |board, action| board.make_move_new(*action));
+                    Some(Job::Position(start, moves, clock))
+                }
+                None => {
+                    writeln!(out, "info string invalid position: {}", line)?;
+                    None
+                }
+            },
+            Some(&"go") => Some(Job::Go {
+                limit: parse_go(&tokens[1..], board.side_to_move()),
+                infinite: tokens.contains(&"infinite"),
+                chess960,
+            }),
+            // The reader thread has already stopped the search
+            Some(&"stop") => None,
+            Some(&"quit") => break,
+            _ => None,
+        };
+        out.flush()?;
+        // The search thread only ends early on an output error, which it returns
+        if let Some(job) = job {
+            if jobs.send(job).is_err() {
+                break;
+            }
+        }
+    }
+    drop(jobs);
+    worker.join().unwrap()
+}
+
+// Seeded fixed-node searches of known positions, so node counts are
+// reproducible and nps can be compared across commits
+fn bench(nodes: f32, seed: u64) {
+    let mut total_nodes = 0.0;
+    let mut total_time = 0.0;
+    for fen in BENCH_POSITIONS.iter() {
+        let board = Board::from_str(fen).unwrap();
+        let mut tree = Tree::new(Evaluator::new(), std::f32::consts::SQRT_2, 0.3)
+            .with_seed(seed)
+            .with_early_exit(false);
+        let start = Instant::now();
+        let results = tree.search(board, Limit::new(None, Some(nodes), None));
+        let time = start.elapsed().as_secs_f32();
+        let searched: f32 = results.iter().map(|(_, visits)| visits).sum();
+        let best = results
+            .iter()
+            .max_by_key(|(_, visits)| OrderedFloat(*visits))
+            .map(|(action, _)| action.to_string())
+            .unwrap_or_default();
+        println!(
+            "{} | bestmove {} | {:.0} nodes | {:.0} nodes/s",
+            fen,
+            best,
+            searched,
+            searched / time
+        );
+        total_nodes += searched;
+        total_time += time;
+    }
+    println!(
+        "{:.0} nodes {:.0} nodes/s ({:.2}s)",
+        total_nodes,
+        total_nodes / total_time,
+        total_time
+    );
+}
+
+// Leaf counts below each root move and in total, to compare against a
+// reference move generator
+fn divide(board: &Board, depth: usize) {
+    let start = Instant::now();
+    let mut total = 0;
+    if depth > 0 {
+        let mut moves: Vec<ChessMove> = MoveGen::new_legal(board).collect();
+        moves.sort();
+        for action in moves {
+            let nodes = perft(&board.make_move_new(action), depth - 1);
+            println!("{}: {}", uci_move(board, &action, false), nodes);
+            total += nodes;
+        }
+    } else {
+        total = 1;
+    }
+    let time = start.elapsed().as_secs_f32();
+    println!(
+        "{} nodes {:.0} nodes/s ({:.2}s)",
+        total,
+        total as f32 / time,
+        time
+    );
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("uci") | Some("--uci") => {
+            if let Err(e) = uci_loop() {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("bench") => {
+            let nodes = args
+                .get(1)
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(BENCH_NODES);
+            let seed = args
+                .get(2)
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(BENCH_SEED);
+            bench(nodes, seed);
+        }
+        Some("perft") => {
+            let depth = match args.get(1).and_then(|d| d.parse().ok()) {
+                Some(depth) => depth,
+                None => {
+                    eprintln!("usage: botfjord perft <depth> [fen]");
+                    std::process::exit(2);
+                }
+            };
+            let board = if args.len() > 2 {
+                match Board::from_str(&args[2..].join(" ")) {
+                    Ok(board) => board,
+                    Err(e) => {
+                        eprintln!("invalid fen: {}", e);
+                        std::process::exit(2);
+                    }
+                }
+            } else {
+                Board::default()
+            };
+            divide(&board, depth);
+        }
+        _ => {
+            eprintln!("usage: botfjord uci | bench [nodes] [seed] | perft <depth> [fen]");
+            std::process::exit(2);
+        }
+    }
+}
@@ -1,27 +1,84 @@
-use chess::{Board, BoardStatus, ChessMove, MoveGen};
+use chess::{Board, BoardStatus, ChessMove, MoveGen, Piece};
 use ordered_float::OrderedFloat;
-use rand::{prelude::*, thread_rng};
+use rand::{distributions::WeightedIndex, prelude::*, rngs::StdRng};
 use rand_distr::Dirichlet;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
 use std::{
     cell::RefCell,
-    collections::HashMap,
-    fmt::{Debug, Formatter, Result},
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    fmt::{Debug, Display, Formatter, Result},
+    hash::BuildHasherDefault,
+    num::NonZeroUsize,
     option::Option,
     rc::{Rc, Weak},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
     time::Instant,
 };
 
-use crate::eval::Evaluator;
+use crate::eval::{insufficient_material, Evaluate, Evaluator, MATE_VALUE};
+
+// Seconds searched when no limit is given
+const DEFAULT_SEARCH_TIME: f32 = 1.0;
+
+// Plies of captures resolved at each leaf, and positions expanded doing so
+const DEFAULT_QUIESCENCE_DEPTH: usize = 2;
+const DEFAULT_QUIESCENCE_NODES: usize = 64;
+
+// Backstop for a search limited only by depth, whose lines may all end in
+// finished games before reaching it
+const DEPTH_LIMIT_MAX_NODES: f32 = 1_000_000.0;
+
+// Share of the root visits one move needs for the search to stop early
+pub const DEFAULT_EARLY_EXIT_RATIO: f32 = 0.9;
+
+// Positions a long-lived tree keeps evaluated between searches
+pub const DEFAULT_CACHE_CAPACITY: usize = 200_000;
+
+// Halfmove clock at which the fifty-move rule ends the game
+const FIFTY_MOVE_PLIES: f32 = 100.0;
+
+// Share of the node cap kept after pruning, so the tree isn't pruned every iteration
+const PRUNE_TARGET: f32 = 0.75;
+
+// Leaves each thread evaluates per batch, so handing out the work is cheap
+// next to the evaluations
+const LEAVES_PER_THREAD: usize = 8;
+// Value in pawns of a selection that hasn't been backed up yet, large enough
+// that the next selection usually takes another line
+const VIRTUAL_LOSS_VALUE: f32 = 3.0;
+
+// Root visits spent on every move before `min_root_prior` starts pruning
+const ROOT_PRUNING_VISITS: f32 = 100.0;
+
+// Values this close to a mate are reported as one, averaging over a few
+// non-mating lines still leaves them far above any material score
+const MATE_SCORE_THRESHOLD: f32 = -MATE_VALUE * 0.9;
+
+// Fixed hasher so branch iteration order (and so tie-breaking) is reproducible
+type MoveMap<V> = HashMap<ChessMove, V, BuildHasherDefault<DefaultHasher>>;
 
 struct Branch {
     prior: f32,
     visit_count: f32,
     total_value: f32,
+    // Sum of squared deviations from the mean backed-up value (Welford)
+    value_m2: f32,
+    mate: bool,
+    // Selections through this branch still waiting to be backed up
+    virtual_loss: f32,
 }
 
+#[derive(Clone, Copy)]
 pub struct Limit {
     time: f32,
     nodes: f32,
+    // Plies from the root of the deepest expanded line
+    depth: f32,
 }
 
 struct Node {
@@ -30,15 +87,269 @@ struct Node {
     parent: Option<Weak<RefCell<Node>>>,
     last_move: Option<Rc<ChessMove>>,
     total_visit_count: f32,
-    branches: HashMap<ChessMove, Branch>,
+    branches: MoveMap<Branch>,
     children: HashMap<Rc<ChessMove>, Rc<RefCell<Node>>>,
+    // Plies since the last capture or pawn move, the game is drawn at FIFTY_MOVE_PLIES
+    halfmove_clock: u32,
+}
+
+// Selected path from the root and the value backed up along it for one iteration
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrajectoryStep {
+    pub path: Vec<ChessMove>,
+    pub value: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Checkmate,
+    Stalemate,
+    Draw,
+}
+
+// Main line of the last search, and how it ends
+#[derive(Clone, Debug, PartialEq)]
+pub struct PvInfo {
+    pub moves: Vec<ChessMove>,
+    // None if the line stops at a leaf that is not a finished game
+    pub terminal: Option<Outcome>,
+    pub depth: usize,
+}
+
+// Progress report passed to the info callback while searching
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchInfo {
+    // Deepest line selected so far
+    pub depth: usize,
+    pub nodes: usize,
+    pub elapsed: f32,
+    pub nps: f32,
+    pub best_move: Option<ChessMove>,
+    // Expected value of the best move for the side to move
+    pub score: UciScore,
+}
+
+// Score as UCI prints it, centipawns or moves to mate, negative when the side
+// to move is losing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UciScore {
+    Cp(i32),
+    Mate(i32),
+}
+
+impl Display for UciScore {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            UciScore::Cp(cp) => write!(f, "cp {}", cp),
+            UciScore::Mate(moves) => write!(f, "mate {}", moves),
+        }
+    }
 }
 
-pub struct Tree {
-    evaluator: Evaluator,
+// Convert a value in pawns for the side to move, near-mate values become a mate
+// `plies` deep
+pub fn to_uci_score(value: f32, plies: usize) -> UciScore {
+    if value.abs() >= MATE_SCORE_THRESHOLD {
+        // Mating plies are odd and mated plies even, both round to full moves
+        let moves = plies.div_ceil(2).max(1) as i32;
+        UciScore::Mate(if value > 0.0 { moves } else { -moves })
+    } else {
+        UciScore::Cp((value * 100.0).round() as i32)
+    }
+}
+
+// Evaluations and priors by position hash, kept across searches until cleared
+#[derive(Default)]
+struct EvalCache {
+    values: HashMap<u64, f32>,
+    priors: HashMap<u64, HashMap<ChessMove, f32>>,
+    // Entries kept before the cache is emptied, 0 disables caching
+    capacity: usize,
+    // Version of the evaluator the entries were computed with
+    version: u64,
+    lookups: usize,
+    hits: usize,
+}
+
+pub struct Tree<E: Evaluate = Evaluator> {
+    root: Option<Rc<RefCell<Node>>>,
+    evaluator: E,
     c: f32,
     noise: f32,
-    rng: ThreadRng,
+    // Share of a uniform distribution blended into the evaluator's priors
+    prior_uniform_mix: f32,
+    // Value unvisited moves at the node's own value when it is losing, instead of 0
+    pessimistic_when_losing: bool,
+    // First play urgency, unvisited moves are valued at the node's own value less
+    // this many pawns. None tries every move once before revisiting any
+    fpu_reduction: Option<f32>,
+    rng: StdRng,
+    stop_on_mate: bool,
+    trajectory: Option<Vec<TrajectoryStep>>,
+    // Keep the root between searches so `advance_root` can carry statistics over
+    reuse_tree: bool,
+    // Stop before the limit once the best root move can no longer change
+    early_exit: bool,
+    early_exit_ratio: f32,
+    cache: EvalCache,
+    on_info: Option<Box<dyn FnMut(SearchInfo)>>,
+    // Seconds between info callbacks
+    info_interval: f32,
+    // Root value above which `best_move` favours moves that reset the halfmove
+    // clock, by up to `progress_bonus` pawns as the clock nears fifty moves
+    progress_threshold: f32,
+    progress_bonus: f32,
+    // Plies of captures resolved before a leaf is evaluated, 0 for a static evaluation
+    quiescence_depth: usize,
+    // Positions expanded while resolving one leaf, so no leaf stalls the search
+    quiescence_nodes: usize,
+    // Nodes kept in the tree before the least visited subtrees are pruned, 0 disables it
+    node_cap: usize,
+    node_count: usize,
+    // Threads evaluating the leaves of a batch, selected from one tree under
+    // virtual loss. 1 searches one leaf at a time
+    threads: usize,
+    // Workers for the leaf evaluations, shared by every tree with as many
+    // threads so searching doesn't spawn any. None uses rayon's global pool
+    pool: Option<Arc<ThreadPool>>,
+    // Leaves each thread evaluated during the last search, by root move
+    worker_visits: Vec<HashMap<ChessMove, f32>>,
+    // Pawns a draw is worth less than 0 to the side to move at the root, and
+    // more to its opponent. Negative when defending, to welcome draws
+    contempt: f32,
+    // Root moves with a lower prior stop being selected once the root has been
+    // explored, 0 keeps every move
+    min_root_prior: f32,
+    // Set from any thread to end the running search, see `stop_handle`
+    stop: Arc<AtomicBool>,
+    // Search claiming an available draw at the root, for callers that can act on
+    // `claims_draw`
+    draw_claims: bool,
+    // Claiming a draw at the root, searched alongside the moves and always valued
+    // at the draw score. None when the game doesn't allow a claim
+    claim: Option<Branch>,
+}
+
+// Position selected for expansion, waiting for its evaluation
+struct Leaf {
+    node: Rc<RefCell<Node>>,
+    action: Rc<ChessMove>,
+    history: Vec<u64>,
+    path: Vec<ChessMove>,
+}
+
+// Static result for a position, before it becomes a node
+struct Evaluation {
+    moves: Vec<ChessMove>,
+    value: f32,
+    priors: HashMap<ChessMove, f32>,
+    // Thread that evaluated it, None for cache hits
+    worker: Option<usize>,
+}
+
+fn evaluate_position<E: Evaluate>(
+    evaluator: &E,
+    state: Board,
+    quiescence_depth: usize,
+    quiescence_nodes: usize,
+) -> Evaluation {
+    let moves: Vec<ChessMove> = MoveGen::new_legal(&state).collect();
+    let value = evaluator.quiescence(&state, quiescence_depth, quiescence_nodes);
+    let priors = evaluator.priors_with_moves(&state, &moves);
+    Evaluation {
+        moves,
+        value,
+        priors,
+        worker: None,
+    }
+}
+
+// Mark every branch from the root down to `action` as selected, so selections
+// made before this one is backed up look elsewhere
+fn add_virtual_loss(node: &Rc<RefCell<Node>>, action: &Rc<ChessMove>) {
+    let mut node = Rc::clone(node);
+    let mut action = Rc::clone(action);
+    loop {
+        node.borrow_mut()
+            .branches
+            .get_mut(&action)
+            .unwrap()
+            .virtual_loss += 1.0;
+        action = match node.borrow().last_move.as_ref() {
+            Some(m) => Rc::clone(m),
+            None => break,
+        };
+        let parent = node.borrow().parent.as_ref().unwrap().upgrade().unwrap();
+        node = parent;
+    }
+}
+
+// Threads to use when `requested` are asked for, 0 meaning one per available core
+pub fn thread_count(requested: usize) -> usize {
+    if requested == 0 {
+        thread::available_parallelism().map_or(1, NonZeroUsize::get)
+    } else {
+        requested
+    }
+}
+
+// Thread pools by size, built the first time a tree asks for one
+static POOLS: Mutex<BTreeMap<usize, Arc<ThreadPool>>> = Mutex::new(BTreeMap::new());
+
+fn shared_pool(threads: usize) -> Option<Arc<ThreadPool>> {
+    let mut pools = POOLS.lock().unwrap();
+    if let Some(pool) = pools.get(&threads) {
+        return Some(Arc::clone(pool));
+    }
+    let pool = Arc::new(ThreadPoolBuilder::new().num_threads(threads).build().ok()?);
+    pools.insert(threads, Arc::clone(&pool));
+    Some(pool)
+}
+
+// Nodes in the subtree below and including `node`
+fn subtree_size(node: &Rc<RefCell<Node>>) -> usize {
+    1 + node
+        .borrow()
+        .children
+        .values()
+        .map(subtree_size)
+        .sum::<usize>()
+}
+
+// Sample a move from search results with probability proportional to
+// visits ^ (1 / temperature), a temperature of 0 plays the most visited move
+pub fn select_move<R: Rng>(
+    results: &[(ChessMove, f32)],
+    temperature: f32,
+    rng: &mut R,
+) -> Option<ChessMove> {
+    // The first of equally visited moves, so a sorted list agrees with its head
+    let most_visited = results
+        .iter()
+        .min_by_key(|(_, visits)| Reverse(OrderedFloat(*visits)))?;
+    if temperature <= 0.0 || most_visited.1 <= 0.0 {
+        return Some(most_visited.0);
+    }
+    // Relative to the most visited move so the powers can't overflow
+    let weights = results
+        .iter()
+        .map(|(_, visits)| (visits / most_visited.1).powf(1.0 / temperature));
+    match WeightedIndex::new(weights) {
+        Ok(index) => Some(results[index.sample(rng)].0),
+        Err(_) => Some(most_visited.0),
+    }
+}
+
+// A position counts as a draw once it repeats within the searched line, or
+// occurs for the third time counting the game before the search
+fn is_repetition(history: &[u64], game_len: usize, hash: u64) -> bool {
+    history[game_len..].contains(&hash) || history.iter().filter(|h| **h == hash).count() >= 2
+}
+
+// Pawn moves and captures reset the fifty-move counter
+pub fn resets_halfmove_clock(state: &Board, action: ChessMove) -> bool {
+    state.piece_on(action.get_source()) == Some(Piece::Pawn)
+        || state.piece_on(action.get_dest()).is_some()
 }
 
 impl Branch {
@@ -47,23 +358,110 @@ impl Branch {
             prior,
             visit_count: 0.0,
             total_value: 0.0,
+            value_m2: 0.0,
+            mate: false,
+            virtual_loss: 0.0,
+        }
+    }
+
+    fn record(&mut self, value: f32) {
+        let old_mean = if self.visit_count == 0.0 {
+            0.0
+        } else {
+            self.total_value / self.visit_count
+        };
+        self.visit_count += 1.0;
+        self.total_value += value;
+        let new_mean = self.total_value / self.visit_count;
+        self.value_m2 += (value - old_mean) * (value - new_mean);
+    }
+
+    // Sample standard deviation of the backed-up values
+    fn value_std_dev(&self) -> f32 {
+        if self.visit_count < 2.0 {
+            return 0.0;
+        }
+        (self.value_m2 / (self.visit_count - 1.0)).max(0.0).sqrt()
+    }
+}
+
+impl EvalCache {
+    // Drop entries computed with other weights than the evaluator's current ones
+    fn sync(&mut self, version: u64) {
+        if self.version != version {
+            self.clear();
+            self.version = version;
+        }
+    }
+
+    fn lookup(&mut self, state: Board) -> Option<(f32, HashMap<ChessMove, f32>)> {
+        if self.capacity == 0 {
+            return None;
+        }
+        self.lookups += 1;
+        let hash = state.get_hash();
+        let value = *self.values.get(&hash)?;
+        let priors = self.priors.get(&hash)?.clone();
+        self.hits += 1;
+        Some((value, priors))
+    }
+
+    fn insert(&mut self, state: Board, value: f32, priors: &HashMap<ChessMove, f32>) {
+        if self.capacity == 0 {
+            return;
+        }
+        // Start over rather than grow without bound over a long game
+        if self.values.len() >= self.capacity {
+            self.clear();
         }
+        let hash = state.get_hash();
+        self.values.insert(hash, value);
+        self.priors.insert(hash, priors.clone());
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.priors.clear();
     }
 }
 
 impl Limit {
-    pub fn new(time: Option<f32>, nodes: Option<f32>) -> Limit {
-        if time.is_none() && nodes.is_none() {
+    // A limit of None or 0 is disabled, the search stops at whichever enabled
+    // limit is reached first. With nothing enabled it falls back to a time budget
+    // instead of running forever, and a depth alone is backed by a node cap
+    pub fn new(time: Option<f32>, nodes: Option<f32>, depth: Option<f32>) -> Limit {
+        let time = time.unwrap_or(0.0).max(0.0);
+        let nodes = nodes.unwrap_or(0.0).max(0.0);
+        let depth = depth.unwrap_or(0.0).max(0.0);
+        if time == 0.0 && nodes == 0.0 {
+            if depth > 0.0 {
+                return Limit {
+                    time,
+                    nodes: DEPTH_LIMIT_MAX_NODES,
+                    depth,
+                };
+            }
             return Limit {
-                time: 0.0,
-                nodes: 0.0,
+                time: DEFAULT_SEARCH_TIME,
+                nodes,
+                depth,
             };
         }
+        Limit { time, nodes, depth }
+    }
+
+    // No limit at all, the search only ends through the tree's stop handle
+    pub fn infinite() -> Limit {
         Limit {
-            time: time.unwrap_or(0.0),
-            nodes: nodes.unwrap_or(0.0),
+            time: 0.0,
+            nodes: 0.0,
+            depth: 0.0,
         }
     }
+
+    fn is_infinite(&self) -> bool {
+        self.time == 0.0 && self.nodes == 0.0 && self.depth == 0.0
+    }
 }
 
 impl Debug for Node {
@@ -83,15 +481,16 @@ impl Node {
         state: Board,
         value: f32,
         priors: HashMap<ChessMove, f32>,
+        moves: &[ChessMove],
         parent: Option<Weak<RefCell<Node>>>,
         last_move: Option<Rc<ChessMove>>,
     ) -> Node {
         let children = HashMap::new();
-        let mut branches = HashMap::new();
-        for action in MoveGen::new_legal(&state) {
+        let mut branches = MoveMap::default();
+        for action in moves {
             // Unwrap is not recommended but we don't want an error to pass silently
-            let prior = priors.get(&action).unwrap();
-            branches.insert(action, Branch::new(*prior));
+            let prior = priors.get(action).unwrap();
+            branches.insert(*action, Branch::new(*prior));
         }
         Node {
             state,
@@ -101,6 +500,16 @@ impl Node {
             total_visit_count: 1.0,
             branches,
             children,
+            halfmove_clock: 0,
+        }
+    }
+
+    // Halfmove clock of the position `action` leads to
+    fn clock_after(&self, action: ChessMove) -> u32 {
+        if resets_halfmove_clock(&self.state, action) {
+            0
+        } else {
+            self.halfmove_clock + 1
         }
     }
 
@@ -130,10 +539,6 @@ impl Node {
         branch.total_value / branch.visit_count
     }
 
-    fn prior(&self, action: &ChessMove) -> f32 {
-        self.branches.get(action).unwrap().prior
-    }
-
     fn visit_count(&self, action: &ChessMove) -> f32 {
         match self.branches.get(action) {
             Some(b) => b.visit_count,
@@ -142,159 +547,1103 @@ impl Node {
     }
 
     fn record_visit(&mut self, action: &ChessMove, value: f32) {
-        let branch = self.branches.get_mut(action).unwrap();
-        branch.visit_count += 1.0;
-        branch.total_value += value;
+        self.branches.get_mut(action).unwrap().record(value);
         self.total_visit_count += 1.0;
     }
 
+    fn remove_virtual_loss(&mut self, action: &ChessMove) {
+        self.branches.get_mut(action).unwrap().virtual_loss -= 1.0;
+    }
+
+    fn set_mate(&mut self, action: &ChessMove) {
+        self.branches.get_mut(action).unwrap().mate = true;
+    }
+
+    // True if the side to move has a proven forced mate
+    fn has_mate(&self) -> bool {
+        self.branches.values().any(|b| b.mate)
+    }
+
+    // True if every legal move leads to a position where the opponent has a proven mate
+    fn is_mated(&self) -> bool {
+        !self.branches.is_empty()
+            && self
+                .branches
+                .keys()
+                .all(|action| match self.children.get(action) {
+                    Some(child) => child.borrow().has_mate(),
+                    None => false,
+                })
+    }
+
+    fn most_visited(&self) -> Option<ChessMove> {
+        self.branches
+            .iter()
+            .filter(|(_, b)| b.visit_count > 0.0)
+            .max_by_key(|(_, b)| OrderedFloat(b.visit_count))
+            .map(|(action, _)| *action)
+    }
+
+    // Plies to checkmate along the proven line, if the side to move has one
+    fn mate_plies(&self) -> Option<usize> {
+        let action = self
+            .branches
+            .iter()
+            .filter(|(_, b)| b.mate)
+            .max_by_key(|(_, b)| OrderedFloat(b.visit_count))?
+            .0;
+        match self.children.get(action) {
+            Some(child) => child.borrow().mated_plies().map(|plies| plies + 1),
+            // The mating position itself is never added, pruned subtrees aren't either
+            None if self.state.make_move_new(*action).status() == BoardStatus::Checkmate => Some(1),
+            None => None,
+        }
+    }
+
+    // Plies to being checkmated against the longest defense, if every move loses
+    fn mated_plies(&self) -> Option<usize> {
+        if !self.is_mated() {
+            return None;
+        }
+        self.children
+            .values()
+            .map(|child| child.borrow().mate_plies().map(|plies| plies + 1))
+            .max()?
+    }
+
+    fn best_is_mate(&self) -> bool {
+        match self
+            .branches
+            .values()
+            .max_by_key(|b| OrderedFloat(b.visit_count))
+        {
+            Some(b) => b.mate,
+            None => false,
+        }
+    }
+
     fn check_visit_counts(&self, rounds: f32) -> bool {
         let mut branches: Vec<_> = self.branches.values().collect();
-        branches.sort_by(|a, b| OrderedFloat(b.visit_count).cmp(&OrderedFloat(a.visit_count)));
-        let remaining_rounds = rounds - self.total_visit_count;
-        branches[0].visit_count >= branches[1].visit_count + remaining_rounds
+        branches.sort_by_key(|b| Reverse(OrderedFloat(b.visit_count)));
+        match branches.len() {
+            // No move to choose between, or the only move is already decided
+            0 => false,
+            1 => true,
+            _ => {
+                let remaining_rounds = rounds - self.total_visit_count;
+                branches[0].visit_count >= branches[1].visit_count + remaining_rounds
+            }
+        }
     }
 
     fn check_visit_ratio(&self, factor: f32, minimum: f32) -> bool {
         if self.total_visit_count < minimum {
             return false;
         }
-        let branches: Vec<_> = self.branches.values().collect();
-        let branch = branches
-            .iter()
+        match self
+            .branches
+            .values()
             .max_by_key(|b| OrderedFloat(b.visit_count))
-            .unwrap();
-        branch.visit_count > self.total_visit_count * factor
+        {
+            Some(branch) => branch.visit_count > self.total_visit_count * factor,
+            None => false,
+        }
     }
 }
 
-impl Tree {
-    pub fn new(evaluator: Evaluator, temperature: f32, noise: f32) -> Tree {
+impl<E: Evaluate> Tree<E> {
+    pub fn new(evaluator: E, temperature: f32, noise: f32) -> Tree<E> {
         Tree {
+            root: None,
             evaluator,
             c: temperature,
             noise,
-            rng: thread_rng(),
+            prior_uniform_mix: 0.0,
+            pessimistic_when_losing: false,
+            fpu_reduction: None,
+            rng: StdRng::from_entropy(),
+            stop_on_mate: false,
+            trajectory: None,
+            reuse_tree: false,
+            early_exit: true,
+            early_exit_ratio: DEFAULT_EARLY_EXIT_RATIO,
+            cache: EvalCache::default(),
+            on_info: None,
+            info_interval: 0.5,
+            progress_threshold: 0.0,
+            progress_bonus: 0.0,
+            quiescence_depth: DEFAULT_QUIESCENCE_DEPTH,
+            quiescence_nodes: DEFAULT_QUIESCENCE_NODES,
+            node_cap: 0,
+            node_count: 0,
+            threads: 1,
+            pool: None,
+            worker_visits: vec![],
+            contempt: 0.0,
+            min_root_prior: 0.0,
+            stop: Arc::new(AtomicBool::new(false)),
+            draw_claims: false,
+            claim: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Tree<E> {
+        self.reseed(seed);
+        self
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Stop searching once the most visited root move is a proven mate
+    pub fn with_stop_on_mate(mut self, stop_on_mate: bool) -> Tree<E> {
+        self.stop_on_mate = stop_on_mate;
+        self
+    }
+
+    pub fn with_prior_uniform_mix(mut self, mix: f32) -> Tree<E> {
+        self.prior_uniform_mix = mix;
+        self
+    }
+
+    pub fn with_pessimistic_when_losing(mut self, pessimistic: bool) -> Tree<E> {
+        self.pessimistic_when_losing = pessimistic;
+        self
+    }
+
+    // Takes precedence over `with_pessimistic_when_losing`
+    pub fn with_fpu_reduction(mut self, reduction: Option<f32>) -> Tree<E> {
+        self.fpu_reduction = reduction;
+        self
+    }
+
+    // Record every iteration of the next searches, see `trajectory`
+    pub fn with_trajectory(mut self, record: bool) -> Tree<E> {
+        self.trajectory = if record { Some(vec![]) } else { None };
+        self
+    }
+
+    // Iterations of the last search, if recording was enabled
+    pub fn trajectory(&self) -> Option<&Vec<TrajectoryStep>> {
+        self.trajectory.as_ref()
+    }
+
+    pub fn with_tree_reuse(mut self, reuse: bool) -> Tree<E> {
+        self.reuse_tree = reuse;
+        self
+    }
+
+    // Make the child reached by `played` the new root, dropping its siblings.
+    // Without an expanded child the next search starts from a fresh node
+    pub fn advance_root(&mut self, played: ChessMove) {
+        let root = match self.root.take() {
+            Some(root) => root,
+            None => return,
+        };
+        if !root.borrow().has_child(&played) {
+            return;
+        }
+        let child = Rc::clone(root.borrow().get_child(&played));
+        {
+            let mut node = child.borrow_mut();
+            node.parent = None;
+            node.last_move = None;
         }
+        self.root = Some(child);
+    }
+
+    // Follow a game from `start` through `moves`, advancing the kept root along
+    // the moves played since the last position it matches
+    pub fn advance_along(&mut self, start: Board, moves: &[ChessMove]) {
+        let root_state = match self.root.as_ref() {
+            Some(root) => root.borrow().state,
+            None => return,
+        };
+        let mut board = start;
+        let mut from = None;
+        for (i, action) in moves.iter().enumerate() {
+            if board == root_state {
+                from = Some(i);
+            }
+            board = board.make_move_new(*action);
+        }
+        if let Some(from) = from {
+            for action in moves[from..].iter() {
+                self.advance_root(*action);
+            }
+        }
+    }
+
+    // Without early exits a node limit always runs exactly that many playouts
+    pub fn with_early_exit(mut self, early_exit: bool) -> Tree<E> {
+        self.early_exit = early_exit;
+        self
+    }
+
+    // Stop once a move holds this share of at least 50000 root visits, values
+    // of 1 or more never stop early this way
+    pub fn with_early_exit_ratio(mut self, ratio: f32) -> Tree<E> {
+        self.early_exit_ratio = ratio;
+        self
+    }
+
+    // Weights changed through here invalidate the evaluation cache
+    pub fn evaluator_mut(&mut self) -> &mut E {
+        &mut self.evaluator
+    }
+
+    pub fn set_evaluator(&mut self, evaluator: E) {
+        self.evaluator = evaluator;
+    }
+
+    // A bonus of 0 disables it
+    pub fn with_progress_bonus(mut self, threshold: f32, bonus: f32) -> Tree<E> {
+        self.progress_threshold = threshold;
+        self.progress_bonus = bonus;
+        self
+    }
+
+    pub fn with_quiescence_depth(mut self, depth: usize) -> Tree<E> {
+        self.quiescence_depth = depth;
+        // Cached values were resolved to the old depth
+        self.cache.clear();
+        self
+    }
+
+    pub fn with_quiescence_nodes(mut self, max_nodes: usize) -> Tree<E> {
+        self.quiescence_nodes = max_nodes;
+        self.cache.clear();
+        self
+    }
+
+    // Search one shared tree with `threads` threads, selections in flight are
+    // counted as losses so the threads spread over different lines. 0 uses
+    // every available core through the global pool, shared by all trees
+    pub fn with_threads(mut self, threads: usize) -> Tree<E> {
+        self.threads = thread_count(threads);
+        self.pool = if threads > 1 {
+            shared_pool(threads)
+        } else {
+            None
+        };
+        self
+    }
+
+    pub fn with_draw_claims(mut self, draw_claims: bool) -> Tree<E> {
+        self.draw_claims = draw_claims;
+        self
+    }
+
+    pub fn with_contempt(mut self, contempt: f32) -> Tree<E> {
+        self.contempt = contempt;
+        self
+    }
+
+    // Flag that ends the search in progress once set, with the results so far.
+    // The search never clears it, the owner resets it before searching again
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    pub fn with_min_root_prior(mut self, min_prior: f32) -> Tree<E> {
+        self.min_root_prior = min_prior;
+        self
+    }
+
+    // Bound the tree to `cap` nodes, pruning back to PRUNE_TARGET of it when exceeded
+    pub fn with_node_cap(mut self, cap: usize) -> Tree<E> {
+        self.node_cap = cap;
+        self
+    }
+
+    // Drop the least visited subtrees off the principal path until the tree is back
+    // under its target size. Branch statistics stay with the parent, so a pruned
+    // move is expanded again if the search returns to it
+    fn prune(&mut self, root: &Rc<RefCell<Node>>) {
+        let target = (self.node_cap as f32 * PRUNE_TARGET) as usize;
+
+        let mut principal = HashSet::new();
+        let mut node = Rc::clone(root);
+        loop {
+            principal.insert(Rc::as_ptr(&node));
+            let child = match node.borrow().most_visited() {
+                Some(action) if node.borrow().has_child(&action) => {
+                    Rc::clone(node.borrow().get_child(&action))
+                }
+                _ => break,
+            };
+            node = child;
+        }
+
+        let mut candidates = vec![];
+        let mut stack = vec![Rc::clone(root)];
+        while let Some(node) = stack.pop() {
+            for (action, child) in node.borrow().children.iter() {
+                stack.push(Rc::clone(child));
+                if !principal.contains(&Rc::as_ptr(child)) {
+                    let visits = node.borrow().visit_count(action);
+                    candidates.push((visits, Rc::clone(&node), **action));
+                }
+            }
+        }
+
+        // Moves inside a subtree have fewer visits than the move into it, so
+        // subtrees are always dropped before anything that contains them
+        candidates.sort_by_key(|(visits, _, _)| OrderedFloat(*visits));
+        for (_, parent, action) in candidates {
+            if self.node_count <= target {
+                break;
+            }
+            if let Some(child) = parent.borrow_mut().children.remove(&action) {
+                self.node_count -= subtree_size(&child);
+            }
+        }
+    }
+
+    // Keep up to `capacity` evaluated positions between searches, 0 disables it
+    pub fn with_eval_cache(mut self, capacity: usize) -> Tree<E> {
+        self.cache.clear();
+        self.cache.capacity = capacity;
+        self
+    }
+
+    // Forget cached evaluations, e.g. when a new game starts
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    // Share of node evaluations served from the cache during the last search
+    pub fn cache_hit_rate(&self) -> f32 {
+        if self.cache.lookups == 0 {
+            return 0.0;
+        }
+        self.cache.hits as f32 / self.cache.lookups as f32
+    }
+
+    // The root move each thread evaluated the most leaves under during the last
+    // search, and how many leaves it evaluated. Cache hits aren't counted
+    pub fn thread_stats(&self) -> Vec<(Option<ChessMove>, f32)> {
+        self.worker_visits
+            .iter()
+            .map(|visits| {
+                let top = visits
+                    .iter()
+                    .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+                    .map(|(m, _)| *m);
+                (top, visits.values().sum())
+            })
+            .collect()
+    }
+
+    // Called every `interval` seconds during a search, and once when it ends
+    pub fn with_on_info(mut self, on_info: Box<dyn FnMut(SearchInfo)>, interval: f32) -> Tree<E> {
+        self.on_info = Some(on_info);
+        self.info_interval = interval;
+        self
+    }
+
+    fn report_info(&mut self, root: &Node, depth: usize, start_time: &Instant) {
+        let on_info = match self.on_info.as_mut() {
+            Some(on_info) => on_info,
+            None => return,
+        };
+        let best_move = root.most_visited();
+        // Proven mates are reported with their exact distance
+        let score = match (root.mate_plies(), root.mated_plies(), best_move) {
+            (Some(plies), _, _) => to_uci_score(-MATE_VALUE, plies),
+            (_, Some(plies), _) => to_uci_score(MATE_VALUE, plies),
+            (_, _, Some(action)) => to_uci_score(root.expected_value(&action), depth),
+            _ => UciScore::Cp(0),
+        };
+        let nodes = root.total_visit_count as usize;
+        let elapsed = start_time.elapsed().as_secs_f32();
+        on_info(SearchInfo {
+            depth,
+            nodes,
+            elapsed,
+            nps: nodes as f32 / elapsed.max(0.000001),
+            best_move,
+            score,
+        });
+    }
+
+    // Evaluations of `states` from the cache, the rest are computed on up to
+    // `threads` threads
+    fn evaluate_positions(&mut self, states: &[Board]) -> Vec<Evaluation> {
+        self.cache.sync(self.evaluator.version());
+        let mut evaluations: Vec<Option<Evaluation>> = states
+            .iter()
+            .map(|state| {
+                self.cache.lookup(*state).map(|(value, priors)| Evaluation {
+                    moves: MoveGen::new_legal(state).collect(),
+                    value,
+                    priors,
+                    worker: None,
+                })
+            })
+            .collect();
+        let missing: Vec<usize> = (0..states.len())
+            .filter(|i| evaluations[*i].is_none())
+            .collect();
+
+        let (depth, max_nodes) = (self.quiescence_depth, self.quiescence_nodes);
+        if self.threads > 1 && missing.len() > 1 {
+            // Idle workers steal leaves from busy ones, so a slow leaf holds up one thread
+            let evaluator = &self.evaluator;
+            let evaluate = || {
+                missing
+                    .par_iter()
+                    .map(|i| {
+                        let mut evaluation =
+                            evaluate_position(evaluator, states[*i], depth, max_nodes);
+                        evaluation.worker = rayon::current_thread_index();
+                        (*i, evaluation)
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let computed = match &self.pool {
+                Some(pool) => pool.install(evaluate),
+                None => evaluate(),
+            };
+            for (i, evaluation) in computed {
+                evaluations[i] = Some(evaluation);
+            }
+        } else {
+            for i in missing.iter() {
+                let mut evaluation =
+                    evaluate_position(&self.evaluator, states[*i], depth, max_nodes);
+                evaluation.worker = Some(0);
+                evaluations[*i] = Some(evaluation);
+            }
+        }
+
+        for i in missing {
+            let evaluation = evaluations[i].as_ref().unwrap();
+            self.cache
+                .insert(states[i], evaluation.value, &evaluation.priors);
+        }
+        evaluations.into_iter().map(Option::unwrap).collect()
     }
 
     fn create_node(
         &mut self,
         state: Board,
+        evaluation: Evaluation,
         action: Option<Rc<ChessMove>>,
         parent: Option<Weak<RefCell<Node>>>,
+        history: &[u64],
     ) -> Node {
-        let mut priors = self.evaluator.priors(state);
-        let value = self.evaluator.evaluate(state);
+        let Evaluation {
+            moves,
+            value,
+            mut priors,
+            ..
+        } = evaluation;
+        let value = self.evaluator.adjust_for_history(value, &state, history);
+
+        // Soften the policy toward uniform to broaden the search
+        if self.prior_uniform_mix != 0.0 && !priors.is_empty() {
+            let uniform = 1.0 / priors.len() as f32;
+            for prior in priors.values_mut() {
+                *prior = (1.0 - self.prior_uniform_mix) * *prior + self.prior_uniform_mix * uniform;
+            }
+        }
 
         // Add Dirichlet noise
         if self.noise != 0.0 {
-            let move_count = MoveGen::new_legal(&state).len();
+            let move_count = moves.len();
             if move_count > 1 {
                 let dirichlet = Dirichlet::new_with_size(self.noise, move_count).unwrap();
                 let samples = dirichlet.sample(&mut self.rng);
                 let mut new_priors: HashMap<ChessMove, f32> = HashMap::new();
-                for ((action, value), noise) in priors.iter().zip(samples) {
-                    new_priors.insert(*action, (value * 0.5) + (noise * 0.5));
+                for (action, noise) in moves.iter().zip(samples) {
+                    new_priors.insert(*action, (priors[action] * 0.5) + (noise * 0.5));
                 }
                 priors = new_priors;
             }
         }
 
-        Node::new(state, value, priors, parent, action)
+        Node::new(state, value, priors, &moves, parent, action)
     }
 
-    fn select_branch(&self, node: &Node) -> ChessMove {
-        let total_n = node.total_visit_count;
+    // None for a finished game, which has no moves to choose from
+    // With a first play urgency an unexplored move is valued a little below the
+    // node itself, and in a lost position it is unlikely to be better than the
+    // node, otherwise every move is tried once before any is revisited.
+    // Values are in pawns, so an unvisited move scored at 0 would rarely
+    // outweigh a good sibling through its prior alone
+    fn unvisited_value(&self, node: &Node) -> Option<f32> {
+        match self.fpu_reduction {
+            Some(reduction) => Some(node.value - reduction),
+            None if self.pessimistic_when_losing && node.value < 0.0 => Some(node.value),
+            None => None,
+        }
+    }
 
-        let score_branch = |action: &ChessMove| {
-            let q = node.expected_value(action);
-            let p = node.prior(action);
-            let n = node.visit_count(action);
-            q + self.c * p * (total_n.ln() / (n + 0.0000001)).sqrt()
+    // PUCT, counting the parent as visited once so the priors order the
+    // children before any of them has been tried. Unvisited moves waiting
+    // for their first visit rank first, highest prior first.
+    // Selections still in flight count as visits that lost
+    fn score_branch(
+        &self,
+        node: &Node,
+        branch: &Branch,
+        unvisited_value: Option<f32>,
+    ) -> (bool, OrderedFloat<f32>) {
+        let exploration = (node.total_visit_count + 1.0).sqrt();
+        let n = branch.visit_count + branch.virtual_loss;
+        let p = branch.prior;
+        let q = match (n == 0.0, unvisited_value) {
+            (true, None) => return (true, OrderedFloat(p)),
+            (true, Some(value)) => value,
+            (false, _) => (branch.total_value - branch.virtual_loss * VIRTUAL_LOSS_VALUE) / n,
         };
+        (
+            false,
+            OrderedFloat(q + self.c * p * exploration / (1.0 + n)),
+        )
+    }
 
-        // Sometimes panicking!
-        match node
-            .moves()
-            .iter()
-            .max_by_key(|m| OrderedFloat(score_branch(m)))
-        {
-            Some(m) => **m,
-            None => {
-                println!("Error: {:?}", node.moves());
-                return *node.moves()[0];
+    fn select_branch(&self, node: &Node) -> Option<ChessMove> {
+        let unvisited_value = self.unvisited_value(node);
+
+        // Only root moves are pruned, deeper nodes keep every move so their
+        // values still converge
+        let prune = self.min_root_prior > 0.0
+            && node.parent.is_none()
+            && node.total_visit_count >= ROOT_PRUNING_VISITS
+            && node
+                .branches
+                .values()
+                .any(|branch| branch.prior >= self.min_root_prior);
+        node.moves()
+            .into_iter()
+            .filter(|m| !prune || node.branches[*m].prior >= self.min_root_prior)
+            .max_by_key(|m| self.score_branch(node, &node.branches[*m], unvisited_value))
+            .copied()
+    }
+
+    // Whether the draw claim outscores every move of the root, never when no
+    // move can be selected, so the search ends instead of claiming forever
+    fn select_claim(&self, root: &Node) -> bool {
+        let claim = match self.claim.as_ref() {
+            Some(claim) => claim,
+            None => return false,
+        };
+        let unvisited_value = self.unvisited_value(root);
+        match self.select_branch(root) {
+            Some(action) => {
+                self.score_branch(root, claim, unvisited_value)
+                    > self.score_branch(root, &root.branches[&action], unvisited_value)
             }
+            None => false,
         }
     }
 
     pub fn search(&mut self, state: Board, limit: Limit) -> Vec<(ChessMove, f32)> {
+        self.search_with_history(state, &[], limit)
+    }
+
+    // Search with the hashes of the game positions that led to `state`, so
+    // repetitions of them are recognised
+    pub fn search_with_history(
+        &mut self,
+        state: Board,
+        game_history: &[u64],
+        limit: Limit,
+    ) -> Vec<(ChessMove, f32)> {
+        self.search_in_game(state, game_history, 0, limit)
+    }
+
+    // `search_with_history` from a position whose halfmove clock, as the FEN
+    // gives it, is already `halfmove_clock`, so fifty-move draws are recognised
+    pub fn search_in_game(
+        &mut self,
+        state: Board,
+        game_history: &[u64],
+        halfmove_clock: u32,
+        limit: Limit,
+    ) -> Vec<(ChessMove, f32)> {
+        let reused = match self.root.take() {
+            Some(root) if self.reuse_tree && root.borrow().state == state => Some(root),
+            _ => None,
+        };
+
+        // Nothing to search in a finished game
+        if state.status() != BoardStatus::Ongoing {
+            return vec![];
+        }
+
         // Return early if only 1 legal move available
         if MoveGen::new_legal(&state).len() == 1 {
             // This looks silly
             return vec![(MoveGen::new_legal(&state).next().unwrap(), 1.0)];
         }
 
+        if let Some(trajectory) = self.trajectory.as_mut() {
+            trajectory.clear();
+        }
+        self.cache.lookups = 0;
+        self.cache.hits = 0;
+        self.worker_visits = vec![HashMap::new(); self.threads.max(1)];
+
         let mut i = 0.0;
+        let mut depth = 0;
         let start_time = Instant::now();
-        let root = Rc::new(RefCell::new(self.create_node(state, None, None)));
-        loop {
-            let mut node = Rc::clone(&root);
-            let mut next_move = Rc::new(self.select_branch(&node.borrow()));
+        let mut last_info = start_time;
+        let root = match reused {
+            Some(root) => root,
+            None => {
+                let evaluation = self.evaluate_positions(&[state]).pop().unwrap();
+                Rc::new(RefCell::new(self.create_node(
+                    state,
+                    evaluation,
+                    None,
+                    None,
+                    game_history,
+                )))
+            }
+        };
+        root.borrow_mut().halfmove_clock = halfmove_clock;
+        if self.node_cap > 0 {
+            self.node_count = subtree_size(&root);
+        }
+        self.claim = if self.draw_claims {
+            self.evaluator
+                .claim_prior(&state, game_history)
+                .map(Branch::new)
+        } else {
+            None
+        };
+        let batch_size = if self.threads > 1 {
+            self.threads * LEAVES_PER_THREAD
+        } else {
+            1
+        };
+        let mut exhausted = false;
+        while !exhausted {
+            let mut leaves: Vec<Leaf> = vec![];
+            while leaves.len() < batch_size
+                && (limit.nodes <= 0.0 || i + (leaves.len() as f32) < limit.nodes)
+            {
+                // Claiming ends the game, so it is backed up without an evaluation.
+                // The batch ends there, so the limits and stop flag are checked
+                // even when the claim keeps being selected
+                if self.select_claim(&root.borrow()) {
+                    self.claim.as_mut().unwrap().record(-self.contempt);
+                    root.borrow_mut().total_visit_count += 1.0;
+                    i += 1.0;
+                    break;
+                }
+                let mut node = Rc::clone(&root);
+                // Finished games are never added to the tree, so every node selected
+                // from has moves, but stop rather than panic if that ever changes
+                let mut next_move = match self.select_branch(&node.borrow()) {
+                    Some(action) => Rc::new(action),
+                    None => {
+                        exhausted = true;
+                        break;
+                    }
+                };
+                let mut path = vec![];
+                // Hashes of the positions along the selected line, for repetitions
+                let mut history = game_history.to_vec();
+                history.push(state.get_hash());
 
-            while node.borrow().has_child(&next_move) {
-                let new_node = Rc::clone(node.borrow().get_child(&next_move));
-                node = new_node;
-                next_move = Rc::new(self.select_branch(&node.borrow()));
+                while node.borrow().has_child(&next_move) {
+                    if self.trajectory.is_some() {
+                        path.push(*next_move);
+                    }
+                    let new_node = Rc::clone(node.borrow().get_child(&next_move));
+                    node = new_node;
+                    history.push(node.borrow().state.get_hash());
+                    next_move = match self.select_branch(&node.borrow()) {
+                        Some(action) => Rc::new(action),
+                        None => {
+                            exhausted = true;
+                            break;
+                        }
+                    };
+                }
+                if exhausted {
+                    break;
+                }
+
+                // Virtual losses didn't steer this selection away from a leaf that
+                // is already waiting, so evaluate what has been gathered
+                if leaves
+                    .iter()
+                    .any(|leaf| Rc::ptr_eq(&leaf.node, &node) && leaf.action == next_move)
+                {
+                    break;
+                }
+                if batch_size > 1 {
+                    add_virtual_loss(&node, &next_move);
+                }
+                leaves.push(Leaf {
+                    node,
+                    action: next_move,
+                    history,
+                    path,
+                });
             }
 
-            let new_state = node.borrow().state.make_move_new(*next_move);
-            let child_node = Rc::new(RefCell::new(self.create_node(
-                new_state,
-                Some(Rc::clone(&next_move)),
-                Some(Rc::downgrade(&node)),
-            )));
-            if new_state.status() == BoardStatus::Ongoing {
-                node.borrow_mut()
-                    .add_child(Rc::clone(&next_move), Rc::clone(&child_node));
+            let states: Vec<Board> = leaves
+                .iter()
+                .map(|leaf| leaf.node.borrow().state.make_move_new(*leaf.action))
+                .collect();
+            let evaluations = self.evaluate_positions(&states);
+
+            for ((leaf, new_state), evaluation) in leaves.into_iter().zip(states).zip(evaluations) {
+                let Leaf {
+                    mut node,
+                    action: next_move,
+                    history,
+                    mut path,
+                } = leaf;
+                let worker = evaluation.worker.map(|w| w % self.worker_visits.len());
+                let child_node = Rc::new(RefCell::new(self.create_node(
+                    new_state,
+                    evaluation,
+                    Some(Rc::clone(&next_move)),
+                    Some(Rc::downgrade(&node)),
+                    &history,
+                )));
+                let clock = node.borrow().clock_after(*next_move);
+                child_node.borrow_mut().halfmove_clock = clock;
+                // Repetitions, fifty moves without progress and positions neither side
+                // can win are scored as draws and, like finished games, never
+                // expanded. A mate still ends the game
+                let repeated = is_repetition(&history, game_history.len(), new_state.get_hash())
+                    || (clock as f32 >= FIFTY_MOVE_PLIES
+                        && new_state.status() == BoardStatus::Ongoing);
+                let dead = insufficient_material(&new_state);
+                if repeated || new_state.status() == BoardStatus::Stalemate || dead {
+                    child_node.borrow_mut().value =
+                        if new_state.side_to_move() == state.side_to_move() {
+                            -self.contempt
+                        } else {
+                            self.contempt
+                        };
+                }
+                if new_state.status() == BoardStatus::Ongoing && !repeated && !dead {
+                    node.borrow_mut()
+                        .add_child(Rc::clone(&next_move), Rc::clone(&child_node));
+                    self.node_count += 1;
+                }
+
+                depth = depth.max(history.len() - game_history.len());
+
+                // The child is evaluated for its own side to move, so negate it to get the
+                // value for the node choosing `action`. Each step up flips the side again,
+                // which leaves root branches valued for the side to move at the root
+                let mut action = Rc::clone(&next_move);
+                let mut value = -child_node.borrow().value;
+                if let Some(trajectory) = self.trajectory.as_mut() {
+                    path.push(*next_move);
+                    trajectory.push(TrajectoryStep { path, value });
+                }
+                let mut mate = new_state.status() == BoardStatus::Checkmate;
+                loop {
+                    node.borrow_mut().record_visit(&action, value);
+                    if batch_size > 1 {
+                        node.borrow_mut().remove_virtual_loss(&action);
+                    }
+                    if mate {
+                        node.borrow_mut().set_mate(&action);
+                    }
+                    action = Rc::clone(match node.borrow().last_move.as_ref() {
+                        Some(m) => m,
+                        None => {
+                            if let Some(worker) = worker {
+                                *self.worker_visits[worker].entry(*action).or_insert(0.0) += 1.0;
+                            }
+                            break;
+                        }
+                    });
+                    let new_node =
+                        Rc::clone(&node.borrow().parent.as_ref().unwrap().upgrade().unwrap());
+                    // Propagate mates upward, the move into a mated node is itself a mate
+                    mate = node.borrow().is_mated();
+                    node = new_node;
+                    value = -value;
+                }
+                i += 1.0;
             }
 
-            let mut action = Rc::clone(&next_move);
-            let mut value = -child_node.borrow().value;
-            loop {
-                node.borrow_mut().record_visit(&action, value);
-                action = Rc::clone(match node.borrow().last_move.as_ref() {
-                    Some(m) => m,
-                    None => break,
-                });
-                let new_node =
-                    Rc::clone(&node.borrow().parent.as_ref().unwrap().upgrade().unwrap());
-                node = new_node;
-                value = -value;
+            // After backing up, so no node on the selected line is still borrowed
+            if self.node_cap > 0 && self.node_count > self.node_cap {
+                self.prune(&root);
             }
 
-            if root.borrow().check_visit_ratio(0.90, 50000.0) {
+            if self.on_info.is_some() && last_info.elapsed().as_secs_f32() >= self.info_interval {
+                self.report_info(&root.borrow(), depth, &start_time);
+                last_info = Instant::now();
+            }
+
+            if self.stop.load(Ordering::Relaxed) {
                 break;
             }
 
-            if limit.nodes > 0.0 {
-                if i >= limit.nodes || root.borrow().check_visit_counts(limit.nodes) {
-                    break;
-                } else {
-                    i += 1.0;
-                }
+            // An infinite search runs until it is stopped, even once the move is clear
+            if limit.is_infinite() {
+                continue;
             }
-            if limit.time > 0.0 {
-                if start_time.elapsed().as_secs_f32() >= limit.time {
-                    break;
-                }
+
+            if self.stop_on_mate && root.borrow().best_is_mate() {
+                break;
+            }
+
+            if self.early_exit
+                && root
+                    .borrow()
+                    .check_visit_ratio(self.early_exit_ratio, 50000.0)
+            {
+                break;
+            }
+
+            if limit.nodes > 0.0
+                && (i >= limit.nodes
+                    || (self.early_exit && root.borrow().check_visit_counts(limit.nodes)))
+            {
+                break;
+            }
+            if limit.time > 0.0 && start_time.elapsed().as_secs_f32() >= limit.time {
+                break;
+            }
+            if limit.depth > 0.0 && depth as f32 >= limit.depth {
+                break;
             }
         }
 
+        self.report_info(&root.borrow(), depth, &start_time);
+
         let mut results = vec![];
         for action in root.borrow().moves() {
             results.push((*action, root.borrow().visit_count(action)));
         }
+        self.root = Some(root);
         results
     }
+
+    // Whether the last search prefers claiming the available draw to every move,
+    // having visited the claim more often than any of them
+    pub fn claims_draw(&self) -> bool {
+        let claim = match self.claim.as_ref() {
+            Some(claim) => claim.visit_count,
+            None => return false,
+        };
+        match self.root.as_ref() {
+            Some(root) => root
+                .borrow()
+                .branches
+                .values()
+                .all(|branch| branch.visit_count < claim),
+            None => false,
+        }
+    }
+
+    // True if the best root move of the last search beats every other explored
+    // move by more than the margin
+    pub fn is_singular(&self, margin_cp: i32) -> bool {
+        let root = match self.root.as_ref() {
+            Some(root) => root.borrow(),
+            None => return false,
+        };
+        let best = match root.most_visited() {
+            Some(action) => action,
+            None => return false,
+        };
+        let best_value = root.expected_value(&best);
+        let margin = margin_cp as f32 / 100.0;
+        root.branches
+            .iter()
+            .filter(|(action, branch)| **action != best && branch.visit_count > 0.0)
+            .all(|(action, _)| best_value - root.expected_value(action) > margin)
+    }
+
+    // Explored root moves of the last search whose value is more than the margin
+    // below the best move, with their loss in centipawns, smallest loss first
+    pub fn inferior_moves(&self, margin_cp: i32) -> Vec<(ChessMove, i32)> {
+        let root = match self.root.as_ref() {
+            Some(root) => root.borrow(),
+            None => return vec![],
+        };
+        let best = match root.most_visited() {
+            Some(action) => action,
+            None => return vec![],
+        };
+        let best_value = root.expected_value(&best);
+        let mut inferior: Vec<(ChessMove, i32)> = root
+            .branches
+            .iter()
+            .filter(|(action, branch)| **action != best && branch.visit_count > 0.0)
+            .map(|(action, _)| {
+                let loss = (best_value - root.expected_value(action)) * 100.0;
+                (*action, loss.round() as i32)
+            })
+            .filter(|(_, loss)| *loss > margin_cp)
+            .collect();
+        inferior.sort_by_key(|(action, loss)| (*loss, *action));
+        inferior
+    }
+
+    // Move to play after the last search, the most visited one unless the
+    // position is won. Then the explored move with the best value is played,
+    // and moves that make progress gain a bonus as the clock runs toward a
+    // fifty-move draw
+    pub fn best_move(&self, halfmove_clock: u32) -> Option<ChessMove> {
+        let root = self.root.as_ref()?.borrow();
+        let best = root.most_visited()?;
+        if self.progress_bonus <= 0.0 || root.expected_value(&best) <= self.progress_threshold {
+            return Some(best);
+        }
+
+        let bonus = self.progress_bonus * (halfmove_clock as f32 / FIFTY_MOVE_PLIES).min(1.0);
+        root.branches
+            .iter()
+            .filter(|(action, branch)| {
+                branch.visit_count > 0.0 && root.expected_value(action) > self.progress_threshold
+            })
+            .max_by_key(|(action, _)| {
+                let mut score = root.expected_value(action);
+                if resets_halfmove_clock(&root.state, **action) {
+                    score += bonus;
+                }
+                OrderedFloat(score)
+            })
+            .map(|(action, _)| *action)
+    }
+
+    // Share of the last search's root visits for every legal root move, unvisited
+    // ones included, in move order. Empty if the search returned without a tree,
+    // as it does when only one move is legal
+    pub fn policy_target(&self) -> Vec<(ChessMove, f32)> {
+        let root = match self.root.as_ref() {
+            Some(root) => root.borrow(),
+            None => return vec![],
+        };
+        // The root's own count includes its expansion, so only branch visits are shared out
+        let total = root
+            .branches
+            .values()
+            .map(|branch| branch.visit_count)
+            .sum::<f32>()
+            .max(1.0);
+        let mut policy: Vec<(ChessMove, f32)> = root
+            .branches
+            .iter()
+            .map(|(action, branch)| (*action, branch.visit_count / total))
+            .collect();
+        policy.sort_by_key(|(action, _)| *action);
+        policy
+    }
+
+    // Mean value backed up through a root move in the last search, for the
+    // side to move at the root
+    pub fn expected_value(&self, action: ChessMove) -> Option<f32> {
+        let root = self.root.as_ref()?.borrow();
+        if !root.branches.contains_key(&action) {
+            return None;
+        }
+        Some(root.expected_value(&action))
+    }
+
+    // Spread of the values backed up through a root move in the last search
+    pub fn value_std_dev(&self, action: ChessMove) -> Option<f32> {
+        let root = self.root.as_ref()?.borrow();
+        root.branches.get(&action).map(Branch::value_std_dev)
+    }
+
+    // Follow the most visited move from the root of the last search
+    pub fn principal_variation(&self) -> PvInfo {
+        let mut moves = vec![];
+        let mut history = vec![];
+        let mut terminal = None;
+        let mut node = match self.root.as_ref() {
+            Some(root) => Rc::clone(root),
+            None => {
+                return PvInfo {
+                    moves,
+                    terminal,
+                    depth: 0,
+                }
+            }
+        };
+
+        loop {
+            history.push(node.borrow().state.get_hash());
+            let action = match node.borrow().most_visited() {
+                Some(action) => action,
+                None => break,
+            };
+            moves.push(action);
+
+            let state = node.borrow().state.make_move_new(action);
+            terminal = match state.status() {
+                BoardStatus::Checkmate => Some(Outcome::Checkmate),
+                BoardStatus::Stalemate => Some(Outcome::Stalemate),
+                BoardStatus::Ongoing => {
+                    let repetitions = history.iter().filter(|h| **h == state.get_hash());
+                    let clock = node.borrow().clock_after(action);
+                    if repetitions.count() >= 2 || clock as f32 >= FIFTY_MOVE_PLIES {
+                        Some(Outcome::Draw)
+                    } else {
+                        None
+                    }
+                }
+            };
+            if terminal.is_some() || !node.borrow().has_child(&action) {
+                break;
+            }
+            let child = Rc::clone(node.borrow().get_child(&action));
+            node = child;
+        }
+
+        let depth = moves.len();
+        PvInfo {
+            moves,
+            terminal,
+            depth,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // Expanded node of `fen` with equal priors, and its legal moves
+    fn expanded(fen: &str) -> (Node, Vec<ChessMove>) {
+        let state = Board::from_str(fen).unwrap();
+        let moves: Vec<ChessMove> = MoveGen::new_legal(&state).collect();
+        let priors = moves.iter().map(|action| (*action, 1.0)).collect();
+        (Node::new(state, 0.0, priors, &moves, None, None), moves)
+    }
+
+    #[test]
+    fn visit_checks_with_one_branch() {
+        // Only Kb8 is legal
+        let (mut node, moves) = expanded("k7/8/1K6/8/8/8/8/7R b - - 0 1");
+        assert_eq!(moves.len(), 1);
+        assert!(node.check_visit_counts(100.0));
+        assert!(!node.check_visit_ratio(0.9, 50.0));
+
+        for _ in 0..60 {
+            node.branches.get_mut(&moves[0]).unwrap().record(0.0);
+            node.total_visit_count += 1.0;
+        }
+        assert!(node.check_visit_counts(100.0));
+        assert!(node.check_visit_ratio(0.9, 50.0));
+    }
+
+    #[test]
+    fn visit_checks_without_branches() {
+        let (node, moves) = expanded("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1");
+        assert!(moves.is_empty());
+        assert!(!node.check_visit_counts(100.0));
+        assert!(!node.check_visit_ratio(0.9, 0.0));
+    }
 }
@@ -0,0 +1,15 @@
+use chess::{Board, MoveGen};
+
+// Number of leaf positions reachable in exactly `depth` plies
+pub fn perft(board: &Board, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = MoveGen::new_legal(board);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves
+        .map(|action| perft(&board.make_move_new(action), depth - 1))
+        .sum()
+}
@@ -0,0 +1,353 @@
+use chess::{Board, ChessMove, Color, Game, GameResult, MoveGen};
+use ordered_float::OrderedFloat;
+use rand::{distributions::WeightedIndex, prelude::*, rngs::StdRng};
+use std::{f32::consts::SQRT_2, str::FromStr, sync::Arc};
+
+use crate::{
+    eval::Evaluator,
+    mcts::{resets_halfmove_clock, select_move, Limit, Tree},
+};
+
+// Plies after which a self-play game is stopped and adjudicated
+pub const MAX_PLIES: usize = 200;
+// Pawns ahead at the ply limit for the game to be adjudicated a win rather than a draw
+const ADJUDICATION_MARGIN: f32 = 5.0;
+// Nodes per move in the gauntlet, so its games are the same on any machine
+const GAUNTLET_NODES: usize = 200;
+const GAUNTLET_RANDOM_PLIES: usize = 4;
+// Won positions push for progress so games aren't drawn by the fifty-move rule
+const PROGRESS_THRESHOLD: f32 = 3.0;
+const PROGRESS_BONUS: f32 = 2.0;
+
+pub struct OpponentResult {
+    pub name: &'static str,
+    pub score: f32,
+    // Approximate Elo of the tested evaluator relative to this opponent
+    pub elo: f32,
+}
+
+pub struct StrengthReport {
+    pub opponents: Vec<OpponentResult>,
+    pub score: f32,
+}
+
+// Share of self-play games won by each color or drawn
+pub struct BalanceReport {
+    pub games: usize,
+    pub white: f32,
+    pub black: f32,
+    pub draws: f32,
+}
+
+// Sample a move weighted by the evaluator's priors instead of searching
+fn sample_prior(evaluator: &Evaluator, state: Board, rng: &mut StdRng) -> ChessMove {
+    let mut priors: Vec<_> = evaluator.priors(&state).into_iter().collect();
+    // Fixed order so a seeded rng picks the same move every time
+    priors.sort_by_key(|(action, _)| *action);
+    let weights = WeightedIndex::new(priors.iter().map(|(_, p)| p.max(0.0000001))).unwrap();
+    priors[weights.sample(rng)].0
+}
+
+fn search_move(
+    tree: &mut Tree,
+    state: Board,
+    history: &[u64],
+    limit: Limit,
+    halfmove_clock: u32,
+) -> ChessMove {
+    let results = tree.search_in_game(state, history, halfmove_clock, limit);
+    match tree.best_move(halfmove_clock) {
+        Some(action) => action,
+        None => {
+            results
+                .iter()
+                .max_by_key(|(_, visits)| OrderedFloat(*visits))
+                .unwrap()
+                .0
+        }
+    }
+}
+
+// Play a full game between two evaluators, the first `opening_random_plies` plies
+// are sampled from the priors so games from the same position can diverge. Later
+// moves are sampled from the search visits at `move_temperature`, see `select_move`.
+// A game still going after `max_plies` plies is adjudicated, see `adjudicate`
+pub fn play_self_game(
+    white: Arc<Evaluator>,
+    black: Arc<Evaluator>,
+    board: Board,
+    time: f32,
+    opening_random_plies: usize,
+    move_temperature: f32,
+    max_plies: usize,
+) -> Game {
+    let limit = Limit::new(Some(time), None, None);
+    play_game(
+        white,
+        black,
+        board,
+        limit,
+        opening_random_plies,
+        move_temperature,
+        max_plies,
+        None,
+    )
+}
+
+// play_self_game searching a fixed number of nodes per move instead of a time,
+// so the same seed plays the same game on any machine
+#[allow(clippy::too_many_arguments)]
+pub fn play_fixed_nodes_game(
+    white: Arc<Evaluator>,
+    black: Arc<Evaluator>,
+    board: Board,
+    nodes: usize,
+    opening_random_plies: usize,
+    move_temperature: f32,
+    max_plies: usize,
+    seed: u64,
+) -> Game {
+    let limit = Limit::new(None, Some(nodes as f32), None);
+    play_game(
+        white,
+        black,
+        board,
+        limit,
+        opening_random_plies,
+        move_temperature,
+        max_plies,
+        Some(seed),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn play_game(
+    white: Arc<Evaluator>,
+    black: Arc<Evaluator>,
+    board: Board,
+    limit: Limit,
+    opening_random_plies: usize,
+    move_temperature: f32,
+    max_plies: usize,
+    seed: Option<u64>,
+) -> Game {
+    let mut game = Game::new_with_board(board);
+    let mut ply = 0;
+    let mut halfmove_clock = 0;
+    // Hashes of the positions played before the current one, for repetitions
+    let mut history = vec![];
+    // Each side keeps its tree and follows both players' moves through it
+    let mut white_tree = Tree::new((*white).clone(), SQRT_2, 0.3)
+        .with_tree_reuse(true)
+        .with_progress_bonus(PROGRESS_THRESHOLD, PROGRESS_BONUS);
+    let mut black_tree = Tree::new((*black).clone(), SQRT_2, 0.3)
+        .with_tree_reuse(true)
+        .with_progress_bonus(PROGRESS_THRESHOLD, PROGRESS_BONUS);
+    let mut rng = match seed {
+        Some(seed) => {
+            white_tree.reseed(seed.wrapping_add(1));
+            black_tree.reseed(seed.wrapping_add(2));
+            StdRng::seed_from_u64(seed)
+        }
+        None => StdRng::from_entropy(),
+    };
+
+    while game.result().is_none() && ply < max_plies {
+        let state = game.current_position();
+        let (evaluator, tree) = match state.side_to_move() {
+            Color::White => (&white, &mut white_tree),
+            Color::Black => (&black, &mut black_tree),
+        };
+
+        let action = if ply < opening_random_plies {
+            sample_prior(evaluator, state, &mut rng)
+        } else if move_temperature > 0.0 {
+            let results = tree.search_in_game(state, &history, halfmove_clock, limit);
+            select_move(&results, move_temperature, &mut rng).unwrap()
+        } else {
+            search_move(tree, state, &history, limit, halfmove_clock)
+        };
+        halfmove_clock = if resets_halfmove_clock(&state, action) {
+            0
+        } else {
+            halfmove_clock + 1
+        };
+        history.push(state.get_hash());
+        game.make_move(action);
+        white_tree.advance_root(action);
+        black_tree.advance_root(action);
+        ply += 1;
+
+        if game.can_declare_draw() {
+            game.declare_draw();
+        }
+    }
+
+    if game.result().is_none() {
+        adjudicate(&mut game, &white, &black);
+    }
+    game
+}
+
+// End an unfinished game by the evaluators' average score of the final position,
+// the side behind by ADJUDICATION_MARGIN resigns and anything closer is drawn
+fn adjudicate(game: &mut Game, white: &Evaluator, black: &Evaluator) {
+    let state = game.current_position();
+    let score = (white.evaluate_both(&state).1 + black.evaluate_both(&state).1) / 2.0;
+    if score >= ADJUDICATION_MARGIN {
+        game.resign(Color::Black);
+    } else if score <= -ADJUDICATION_MARGIN {
+        game.resign(Color::White);
+    } else {
+        game.offer_draw(state.side_to_move());
+        game.accept_draw();
+    }
+}
+
+// Score of a finished game from `color`'s point of view, unfinished games count as draws
+pub fn game_score(game: &Game, color: Color) -> f32 {
+    match game.result() {
+        Some(GameResult::WhiteCheckmates) | Some(GameResult::BlackResigns) => match color {
+            Color::White => 1.0,
+            Color::Black => 0.0,
+        },
+        Some(GameResult::BlackCheckmates) | Some(GameResult::WhiteResigns) => match color {
+            Color::White => 0.0,
+            Color::Black => 1.0,
+        },
+        _ => 0.5,
+    }
+}
+
+// Elo difference implied by a score fraction
+pub fn elo_difference(score: f32) -> f32 {
+    let score = score.clamp(0.01, 0.99);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+// Score of `evaluator` over `games` games against `opponent`, alternating
+// colors. Game `i` is seeded with `i`, so the score is repeatable
+pub fn play_match(evaluator: &Arc<Evaluator>, opponent: &Arc<Evaluator>, games: usize) -> f32 {
+    let mut points = 0.0;
+    for i in 0..games {
+        let (white, black, color) = if i % 2 == 0 {
+            (Arc::clone(evaluator), Arc::clone(opponent), Color::White)
+        } else {
+            (Arc::clone(opponent), Arc::clone(evaluator), Color::Black)
+        };
+        let game = play_fixed_nodes_game(
+            white,
+            black,
+            Board::default(),
+            GAUNTLET_NODES,
+            GAUNTLET_RANDOM_PLIES,
+            0.0,
+            MAX_PLIES,
+            i as u64,
+        );
+        points += game_score(&game, color);
+    }
+    points / games.max(1) as f32
+}
+
+// Play the evaluator against a fixed ladder of baselines, see `play_match`
+pub fn estimate_strength(evaluator: Arc<Evaluator>, games_per_opponent: usize) -> StrengthReport {
+    let ladder = vec![
+        ("material", Evaluator::material_only()),
+        ("hand-tuned", Evaluator::new()),
+        ("random-prior", Evaluator::uniform_prior()),
+    ];
+
+    let mut opponents = vec![];
+    let mut total = 0.0;
+    for (name, opponent) in ladder {
+        let score = play_match(&evaluator, &Arc::new(opponent), games_per_opponent);
+        total += score;
+        opponents.push(OpponentResult {
+            name,
+            score,
+            elo: elo_difference(score),
+        });
+    }
+
+    let score = total / opponents.len() as f32;
+    StrengthReport { opponents, score }
+}
+
+// Play the evaluator against itself from each opening FEN to look for a color bias.
+// Games are seeded in order from `seed`, so the report is repeatable
+pub fn self_play_balance(
+    evaluator: Arc<Evaluator>,
+    openings: &[String],
+    games_each: usize,
+    seed: u64,
+) -> Result<BalanceReport, chess::Error> {
+    let boards = openings
+        .iter()
+        .map(|fen| Board::from_str(fen))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (mut white, mut black, mut draws) = (0, 0, 0);
+    for (i, board) in boards.into_iter().enumerate() {
+        for j in 0..games_each {
+            let game = play_fixed_nodes_game(
+                Arc::clone(&evaluator),
+                Arc::clone(&evaluator),
+                board,
+                GAUNTLET_NODES,
+                GAUNTLET_RANDOM_PLIES,
+                0.0,
+                MAX_PLIES,
+                seed.wrapping_add((i * games_each + j) as u64),
+            );
+            match game.result() {
+                Some(GameResult::WhiteCheckmates) | Some(GameResult::BlackResigns) => white += 1,
+                Some(GameResult::BlackCheckmates) | Some(GameResult::WhiteResigns) => black += 1,
+                // Drawn, including games adjudicated as draws at the ply limit
+                _ => draws += 1,
+            }
+        }
+    }
+
+    let games = white + black + draws;
+    let share = |count: usize| count as f32 / games.max(1) as f32;
+    Ok(BalanceReport {
+        games,
+        white: share(white),
+        black: share(black),
+        draws: share(draws),
+    })
+}
+
+// Fraction of positions where the move with the best static evaluation is also
+// the most visited move after searching, low agreement means the evaluation
+// misleads the search. Each position is searched `nodes` nodes with a seed
+// counted up from `seed`, so the result is repeatable
+pub fn eval_vs_search_agreement(
+    evaluator: &Evaluator,
+    positions: &[Board],
+    nodes: usize,
+    seed: u64,
+) -> f32 {
+    let mut agreed = 0;
+    let mut compared = 0;
+    for (i, state) in positions.iter().copied().enumerate() {
+        // Each resulting position is evaluated for the opponent, lowest is best
+        let static_best = match MoveGen::new_legal(&state)
+            .min_by_key(|action| OrderedFloat(evaluator.evaluate(&state.make_move_new(*action))))
+        {
+            Some(action) => action,
+            None => continue,
+        };
+        let mut tree =
+            Tree::new(evaluator.clone(), SQRT_2, 0.3).with_seed(seed.wrapping_add(i as u64));
+        let limit = Limit::new(None, Some(nodes as f32), None);
+        let search_best = search_move(&mut tree, state, &[], limit, 0);
+
+        compared += 1;
+        if static_best == search_best {
+            agreed += 1;
+        }
+    }
+    agreed as f32 / compared.max(1) as f32
+}
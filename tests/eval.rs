@@ -0,0 +1,382 @@
+use chess::{Board, ChessMove, Piece};
+use mcts_rust::eval::{
+    insufficient_material, Evaluator, BISHOP_PAIR_VALUES, CENTER_CONTROL, CONNECTED_PAWN,
+    EG_PIECE_VALUES, MATE_VALUE, MOBILITY_WEIGHT, PARAMETER_COUNT, PHALANX_PAWN, PIECE_VALUES,
+    TRADE_WEIGHT,
+};
+use std::{str::FromStr, time::Instant};
+
+// Same pawns on the same ranks, held together or spread over the board
+const CONNECTED: &str = "4k3/8/8/8/4PP2/6P1/8/4K3 w - - 0 1";
+const SCATTERED: &str = "4k3/8/8/8/1P2P3/6P1/8/4K3 w - - 0 1";
+
+fn structure_gap(evaluator: &Evaluator) -> f32 {
+    let connected = Board::from_str(CONNECTED).unwrap();
+    let scattered = Board::from_str(SCATTERED).unwrap();
+    evaluator.evaluate(&connected) - evaluator.evaluate(&scattered)
+}
+
+#[test]
+fn connected_pawns_beat_scattered() {
+    let evaluator = Evaluator::new();
+    assert!(structure_gap(&evaluator) > 0.0);
+
+    let mut without_chains = Evaluator::new();
+    let mut parameters = without_chains.parameters();
+    parameters[CONNECTED_PAWN] = 0.0;
+    parameters[PHALANX_PAWN] = 0.0;
+    without_chains.set_parameters(&parameters);
+    assert!(structure_gap(&evaluator) > structure_gap(&without_chains));
+}
+
+// A rook down for White, with either side to move
+const ROOK_DOWN: [&str; 2] = [
+    "r3k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1",
+    "r3k3/pppp4/8/8/8/8/PPPP4/4K3 b - - 0 1",
+];
+
+#[test]
+fn repetition_pulls_toward_draw() {
+    let evaluator = Evaluator::new();
+    for fen in ROOK_DOWN.iter() {
+        let board = Board::from_str(fen).unwrap();
+        let fresh = evaluator.evaluate_with_history(&board, &[]);
+        let repeated = evaluator.evaluate_with_history(&board, &[board.get_hash()]);
+        // The defender welcomes the repetition and the side ahead avoids it
+        assert!(repeated.abs() < fresh.abs(), "{}", fen);
+        assert_eq!(repeated.signum(), fresh.signum(), "{}", fen);
+    }
+}
+
+#[test]
+fn uniform_prior_keeps_evaluation() {
+    let board = Board::from_str(ROOK_DOWN[0]).unwrap();
+    let uniform = Evaluator::uniform_prior();
+    assert_eq!(uniform.evaluate(&board), Evaluator::new().evaluate(&board));
+    let priors = uniform.priors(&board);
+    let first = priors.values().next().copied().unwrap();
+    assert!(priors.values().all(|prior| *prior == first));
+}
+
+#[test]
+fn half_material_tapers_halfway() {
+    // Each side has lost its queen, a knight and a bishop
+    let middlegame =
+        Board::from_str("r1b1k2r/pppp1ppp/2n5/8/8/2N5/PPPP1PPP/R1B1K2R w KQkq - 0 1").unwrap();
+    assert!((Evaluator::game_phase(&middlegame) - 0.5).abs() < 1e-5);
+
+    // Piece values land halfway between the opening and the pawn ending
+    let evaluator = Evaluator::new();
+    let opening = evaluator.tapered_piece_values(&Board::default());
+    let ending = evaluator.tapered_piece_values(&Board::from_str(CONNECTED).unwrap());
+    for (piece, value) in evaluator.tapered_piece_values(&middlegame) {
+        assert!((value - (opening[&piece] + ending[&piece]) / 2.0).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn gradient_of_unused_parameter() {
+    // Piece values run pawn, bishop, knight, rook, queen
+    let (rook, queen) = (3, 4);
+    // No queens on the board, so the queen values don't matter
+    let board = Board::from_str(ROOK_DOWN[0]).unwrap();
+    let gradient = Evaluator::new().numeric_gradient(&board, 0.01);
+    assert_eq!(gradient.len(), PARAMETER_COUNT);
+    assert!(gradient[PIECE_VALUES + queen].abs() < 1e-3);
+    assert!(gradient[EG_PIECE_VALUES + queen].abs() < 1e-3);
+    // Black's rook is on the board, its middlegame value counts against White
+    assert!(gradient[PIECE_VALUES + rook] < 0.0);
+}
+
+#[test]
+fn blend_weights() {
+    let a = Evaluator::new();
+    let b = Evaluator::material_only();
+    let close = |x: &[f32], y: &[f32]| x.iter().zip(y).all(|(x, y)| (x - y).abs() < 1e-5);
+    assert!(close(
+        &Evaluator::blend(&a, &b, 1.0).parameters(),
+        &a.parameters()
+    ));
+    assert!(close(
+        &Evaluator::blend(&a, &b, 0.0).parameters(),
+        &b.parameters()
+    ));
+    let midpoint: Vec<f32> = a
+        .parameters()
+        .iter()
+        .zip(b.parameters())
+        .map(|(a, b)| (a + b) / 2.0)
+        .collect();
+    assert!(close(
+        &Evaluator::blend(&a, &b, 0.5).parameters(),
+        &midpoint
+    ));
+}
+
+#[test]
+fn trading_when_ahead() {
+    // White is a knight up either way, with or without the rooks still on
+    let fewer = Board::from_str("4k3/pppp4/8/8/8/8/PPPP4/1N2K3 w - - 0 1").unwrap();
+    let more = Board::from_str("r3k3/pppp4/8/8/8/8/PPPP4/RN2K3 w - - 0 1").unwrap();
+    // Material only apart from the trade bonus
+    let mut evaluator = Evaluator::material_only();
+    let mut parameters = evaluator.parameters();
+    parameters[TRADE_WEIGHT] = 0.05;
+    evaluator.set_parameters(&parameters);
+    let gain = evaluator.evaluate(&fewer) - evaluator.evaluate(&more);
+    assert!(gain > 0.0 && gain < 0.5, "{}", gain);
+}
+
+#[test]
+fn queen_promotion_prior() {
+    let board = Board::from_str("8/4P3/8/8/8/k7/8/4K3 w - - 0 1").unwrap();
+    let priors = Evaluator::new().priors(&board);
+    let queen = ChessMove::from_str("e7e8q").unwrap();
+    let knight = ChessMove::from_str("e7e8n").unwrap();
+    assert!(priors[&queen] > priors[&knight]);
+}
+
+// Positions with pieces on, checks and no checks, for the null-move tests
+const MIDDLEGAMES: [&str; 3] = [
+    "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    "r2qk2r/ppp2ppp/2np1n2/2b1p1B1/2B1P1b1/2NP1N2/PPP2PPP/R2QK2R b KQkq - 0 7",
+    "rnbqkbnr/ppp2ppp/8/1B1pp3/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 1 3",
+];
+
+#[test]
+fn null_move_skipped_without_opponent_terms() {
+    // Center control and mobility are the terms that need
+    // the opponent's moves. Zeroed, the null move is skipped, and weights too
+    // small to matter still run it
+    let skipped = Evaluator::material_only();
+    let mut parameters = skipped.parameters();
+    parameters[CENTER_CONTROL] = 1e-7;
+    parameters[MOBILITY_WEIGHT] = 1e-7;
+    let mut runs = Evaluator::material_only();
+    runs.set_parameters(&parameters);
+    for fen in MIDDLEGAMES.iter() {
+        let board = Board::from_str(fen).unwrap();
+        assert!(
+            (skipped.evaluate(&board) - runs.evaluate(&board)).abs() < 1e-4,
+            "{}",
+            fen
+        );
+    }
+}
+
+// Evaluations per second with and without the null move, run with
+// `cargo test --release --test eval -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn null_move_benchmark() {
+    let boards: Vec<Board> = MIDDLEGAMES
+        .iter()
+        .map(|fen| Board::from_str(fen).unwrap())
+        .collect();
+    let rate = |evaluator: &Evaluator| {
+        let start = Instant::now();
+        let mut total = 0.0;
+        for _ in 0..100_000 {
+            for board in boards.iter() {
+                total += evaluator.evaluate(board);
+            }
+        }
+        let rate = (100_000 * boards.len()) as f32 / start.elapsed().as_secs_f32();
+        (rate, total)
+    };
+    let mut with_null_move = Evaluator::material_only();
+    let mut parameters = with_null_move.parameters();
+    parameters[CENTER_CONTROL] = 1e-7;
+    with_null_move.set_parameters(&parameters);
+    let (slow, _) = rate(&with_null_move);
+    let (fast, _) = rate(&Evaluator::material_only());
+    println!(
+        "null move {:.0} evals/s | skipped {:.0} evals/s | {:.2}x",
+        slow,
+        fast,
+        fast / slow
+    );
+}
+
+#[test]
+fn evaluate_both_perspectives() {
+    let evaluator = Evaluator::new();
+    let white = Board::from_str(ROOK_DOWN[0]).unwrap();
+    let (to_move, white_view) = evaluator.evaluate_both(&white);
+    assert_eq!(to_move, white_view);
+    assert!(white_view < 0.0);
+
+    let black = Board::from_str(ROOK_DOWN[1]).unwrap();
+    let (to_move, white_view) = evaluator.evaluate_both(&black);
+    assert_eq!(to_move, -white_view);
+    assert!(white_view < 0.0);
+}
+
+#[test]
+fn prior_eval_mix() {
+    // No captures or checks, so the material priors are all the same
+    let board = Board::default();
+    let material = Evaluator::new().priors(&board);
+    let mut evaluator = Evaluator::new();
+    evaluator.set_prior_eval_mix(0.0);
+    assert_eq!(evaluator.priors(&board), material);
+
+    // Fully mixed, a move is preferred exactly when it leaves the opponent worse off
+    evaluator.set_prior_eval_mix(1.0);
+    let priors = evaluator.priors(&board);
+    let value = |action: &ChessMove| evaluator.evaluate(&board.make_move_new(*action));
+    for (a, prior_a) in priors.iter() {
+        for (b, prior_b) in priors.iter() {
+            if value(a) < value(b) {
+                assert!(prior_a > prior_b, "{} {}", a, b);
+            }
+        }
+    }
+}
+
+#[test]
+fn tapered_values_at_the_ends() {
+    // Middlegame and endgame values both run pawn, bishop, knight, rook, queen
+    let evaluator = Evaluator::new();
+    let parameters = evaluator.parameters();
+    let pieces = [
+        Piece::Pawn,
+        Piece::Bishop,
+        Piece::Knight,
+        Piece::Rook,
+        Piece::Queen,
+    ];
+    let opening = evaluator.tapered_piece_values(&Board::default());
+    let bare_kings = Board::from_str("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    let ending = evaluator.tapered_piece_values(&bare_kings);
+    for (i, piece) in pieces.iter().enumerate() {
+        assert_eq!(opening[piece], parameters[PIECE_VALUES + i]);
+        assert_eq!(ending[piece], parameters[EG_PIECE_VALUES + i]);
+    }
+}
+
+#[test]
+fn doubled_and_isolated_pawns() {
+    // White's c-pawns are stacked and its d-pawn cut off, against the same
+    // pawns held together
+    let broken = Board::from_str("4k3/pp3ppp/8/8/8/2P5/2P1P3/4K3 w - - 0 1").unwrap();
+    let clean = Board::from_str("4k3/pp3ppp/8/8/8/8/2PPP3/4K3 w - - 0 1").unwrap();
+    let evaluator = Evaluator::new();
+    assert!(evaluator.evaluate(&broken) < evaluator.evaluate(&clean));
+}
+
+#[test]
+fn bishop_pair() {
+    // White gave up a knight for Black's bishop and keeps both of its own
+    let board =
+        Board::from_str("rn1qkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq - 0 1").unwrap();
+    // Middlegame and endgame bishop pair bonuses
+    let (middlegame, endgame) = (BISHOP_PAIR_VALUES, BISHOP_PAIR_VALUES + 1);
+    let evaluator = Evaluator::new();
+    let mut parameters = evaluator.parameters();
+    let phase = Evaluator::game_phase(&board);
+    let bonus = parameters[middlegame] * phase + parameters[endgame] * (1.0 - phase);
+    parameters[middlegame] = 0.0;
+    parameters[endgame] = 0.0;
+    let mut without_pair = Evaluator::new();
+    without_pair.set_parameters(&parameters);
+    let gain = evaluator.evaluate(&board) - without_pair.evaluate(&board);
+    assert!((gain - bonus).abs() < 1e-4, "{} {}", gain, bonus);
+}
+
+#[test]
+fn terminal_values() {
+    let evaluator = Evaluator::new();
+    // Fool's mate, White is mated
+    let mated =
+        Board::from_str("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+    assert_eq!(evaluator.evaluate(&mated), MATE_VALUE);
+    // Black to move has no moves but isn't in check
+    let stalemate = Board::from_str("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1").unwrap();
+    assert_eq!(evaluator.evaluate(&stalemate), 0.0);
+}
+
+#[test]
+fn insufficient_material_draws() {
+    let evaluator = Evaluator::new();
+    let drawn = [
+        // Bare kings
+        "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+        // A lone knight or bishop
+        "4k3/8/8/8/8/8/8/4KN2 w - - 0 1",
+        "4k3/8/8/8/8/8/8/4KB2 b - - 0 1",
+        "4kb2/8/8/8/8/8/8/4K3 w - - 0 1",
+        // A bishop each, on squares of the same color
+        "2b1k3/8/8/8/8/8/8/4KB2 w - - 0 1",
+    ];
+    for fen in drawn.iter() {
+        let board = Board::from_str(fen).unwrap();
+        assert!(insufficient_material(&board), "{}", fen);
+        assert_eq!(evaluator.evaluate(&board), 0.0, "{}", fen);
+    }
+
+    let playable = [
+        // Bishops on opposite colors can still mate in a corner
+        "5b2/4k3/8/8/8/8/8/4KB2 w - - 0 1",
+        "4k3/8/8/8/8/8/8/3NKN2 w - - 0 1",
+        "4k3/8/8/8/8/8/8/4KR2 w - - 0 1",
+        "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+    ];
+    for fen in playable.iter() {
+        let board = Board::from_str(fen).unwrap();
+        assert!(!insufficient_material(&board), "{}", fen);
+    }
+}
+
+#[test]
+fn rook_on_open_file() {
+    // The same rook lifted to the open d-file, or kept behind its own pawn on a1
+    let open = Board::from_str("4k3/pp3ppp/8/8/8/8/PP3PPP/3RK3 w - - 0 1").unwrap();
+    let closed = Board::from_str("4k3/pp3ppp/8/8/8/8/PP3PPP/R3K3 w - - 0 1").unwrap();
+    let evaluator = Evaluator::new();
+    assert!(evaluator.evaluate(&open) > evaluator.evaluate(&closed));
+}
+
+#[test]
+fn see_of_defended_pawn() {
+    // The e5 pawn is defended by the d6 pawn
+    let board = Board::from_str("4k3/8/3p4/4p3/8/8/8/4QK2 w - - 0 1").unwrap();
+    let evaluator = Evaluator::new();
+    let queen_takes = ChessMove::from_str("e1e5").unwrap();
+    assert!(evaluator.see(&board, queen_takes) < 0.0);
+
+    // Taken by a pawn it's an even trade
+    let board = Board::from_str("4k3/8/3p4/4p3/3P4/8/8/4K3 w - - 0 1").unwrap();
+    let pawn_takes = ChessMove::from_str("d4e5").unwrap();
+    assert_eq!(evaluator.see(&board, pawn_takes), 0.0);
+}
+
+#[test]
+fn forcing_moves_ordered_first() {
+    // Rxd5 wins the queen and Rh8+ checks, Rh2 does neither
+    let board = Board::from_str("4k3/8/8/3q4/8/8/8/3RK2R w K - 0 1").unwrap();
+    let priors = Evaluator::new().priors(&board);
+    let prior = |text: &str| priors[&ChessMove::from_str(text).unwrap()];
+    assert!(prior("d1d5") > prior("h1h2"));
+    assert!(prior("h1h8") > prior("h1h2"));
+
+    // Turning the factors off, with the check bonus kept, shrinks the capture's
+    // and the check's shares and grows the quiet move's
+    let mut unboosted = Evaluator::new();
+    unboosted.set_forcing_prior_factors(1.0, 1.0);
+    let flat = unboosted.priors(&board);
+    let flat_prior = |text: &str| flat[&ChessMove::from_str(text).unwrap()];
+    assert!(flat_prior("d1d5") < prior("d1d5"));
+    assert!(flat_prior("h1h8") < prior("h1h8"));
+    assert!(flat_prior("h1h2") > prior("h1h2"));
+}
+
+#[test]
+fn en_passant_capture_boosted() {
+    // exd6 takes the pawn that just moved past e5, no move gives check
+    let board = Board::from_str("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+    let capture = ChessMove::from_str("e5d6").unwrap();
+    let mut unboosted = Evaluator::new();
+    unboosted.set_forcing_prior_factors(1.0, 1.0);
+    assert!(unboosted.priors(&board)[&capture] < Evaluator::new().priors(&board)[&capture]);
+}
@@ -0,0 +1,41 @@
+use chess::Board;
+use mcts_rust::perft::perft;
+use std::str::FromStr;
+
+// (fen, leaf counts at depths 1 to 4) from the reference tables
+const POSITIONS: [(&str, [u64; 4]); 4] = [
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        [20, 400, 8902, 197281],
+    ),
+    // Castling, pins and en passant
+    (
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        [48, 2039, 97862, 4085603],
+    ),
+    (
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        [14, 191, 2812, 43238],
+    ),
+    // Promotions
+    (
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        [6, 264, 9467, 422333],
+    ),
+];
+
+#[test]
+fn perft_matches_reference_counts() {
+    for (fen, counts) in POSITIONS.iter() {
+        let board = Board::from_str(fen).unwrap();
+        for (depth, expected) in counts.iter().enumerate() {
+            assert_eq!(
+                perft(&board, depth + 1),
+                *expected,
+                "{} depth {}",
+                fen,
+                depth + 1
+            );
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use chess::{Board, ChessMove};
+use mcts_rust::{
+    eval::{Evaluator, MATE_VALUE},
+    mcts::{Limit, Outcome, Tree, DEFAULT_CACHE_CAPACITY},
+    uci,
+};
+use ordered_float::OrderedFloat;
+use std::{f32::consts::SQRT_2, str::FromStr, time::Instant};
+
+const SEED: u64 = 0;
+
+// Root visits of a search, including any carried over
+fn visits(results: &[(ChessMove, f32)]) -> f32 {
+    results.iter().map(|(_, visits)| visits).sum()
+}
+
+#[test]
+fn tree_reuse_keeps_statistics() {
+    let start = Board::default();
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+        .with_seed(SEED)
+        .with_early_exit(false)
+        .with_tree_reuse(true);
+    tree.search(start, Limit::new(None, Some(2000.0), None));
+
+    // Our move and the expected reply, both explored in the first search
+    let line: Vec<_> = tree.principal_variation().moves[..2].to_vec();
+    tree.advance_along(start, &line);
+    let board = line
+        .iter()
+        .fold(start, |board, action| board.make_move_new(*action));
+    let results = tree.search(board, Limit::new(None, Some(100.0), None));
+    assert!(visits(&results) > 100.0);
+
+    // A position behind the kept root starts over
+    tree.advance_along(start, &[]);
+    let results = tree.search(start, Limit::new(None, Some(100.0), None));
+    assert!(visits(&results) <= 100.0);
+}
+
+// Value of the most visited move, searched with the given halfmove clock
+fn value_at_clock(fen: &str, clock: u32) -> (String, f32) {
+    let board = Board::from_str(fen).unwrap();
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3).with_seed(SEED);
+    let results = tree.search_in_game(board, &[], clock, Limit::new(None, Some(1000.0), None));
+    let (action, _) = results
+        .iter()
+        .max_by_key(|(_, visits)| OrderedFloat(*visits))
+        .unwrap();
+    (uci(action), tree.expected_value(*action).unwrap())
+}
+
+#[test]
+fn fifty_move_draw() {
+    // A rook up, but every move without a capture or pawn move draws
+    let rook_up = "8/8/8/4k3/8/8/8/R3K3 w - - 0 1";
+    assert!(value_at_clock(rook_up, 0).1 > 3.0);
+    assert!(value_at_clock(rook_up, 99).1.abs() < 0.5);
+
+    // Mating on the hundredth ply still wins
+    let (action, value) = value_at_clock("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 99);
+    assert_eq!(action, "a1a8");
+    assert!(value >= -MATE_VALUE * 0.9);
+}
+
+#[test]
+fn stop_on_proven_mate() {
+    // Mate in two, the search can stop long before the node limit
+    let board = Board::from_str("k7/8/2K5/8/8/8/8/7R w - - 0 1").unwrap();
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+        .with_seed(SEED)
+        .with_early_exit(false)
+        .with_stop_on_mate(true);
+    let results = tree.search(board, Limit::new(None, Some(100_000.0), None));
+    assert!(visits(&results) < 10_000.0);
+    assert_eq!(
+        tree.principal_variation().terminal,
+        Some(Outcome::Checkmate)
+    );
+}
+
+#[test]
+fn single_legal_move() {
+    // Only the king can move, searching it must not panic
+    let board = Board::from_str("k7/8/1K6/8/8/8/8/7R b - - 0 1").unwrap();
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3).with_seed(SEED);
+    let results = tree.search(board, Limit::new(None, Some(1000.0), None));
+    assert_eq!(results.len(), 1);
+    assert_eq!(uci(&results[0].0), "a8b8");
+}
+
+#[test]
+fn trajectory_is_repeatable() {
+    let run = || {
+        let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+            .with_seed(SEED)
+            .with_trajectory(true);
+        tree.search(Board::default(), Limit::new(None, Some(500.0), None));
+        tree.trajectory().unwrap().clone()
+    };
+    let trajectory = run();
+    assert!(!trajectory.is_empty());
+    assert_eq!(trajectory, run());
+}
+
+#[test]
+fn reseeding_repeats_the_search() {
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3);
+    let limit = Limit::new(None, Some(500.0), None);
+    tree.reseed(SEED);
+    let first = tree.search(Board::default(), limit);
+    tree.reseed(SEED);
+    assert_eq!(first, tree.search(Board::default(), limit));
+}
+
+#[test]
+fn principal_variation_terminal() {
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3).with_seed(SEED);
+    let mate = Board::from_str("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+    tree.search(mate, Limit::new(None, Some(1000.0), None));
+    let pv = tree.principal_variation();
+    assert_eq!(pv.terminal, Some(Outcome::Checkmate));
+    assert_eq!(pv.depth, 1);
+
+    tree.search(Board::default(), Limit::new(None, Some(1000.0), None));
+    let pv = tree.principal_variation();
+    assert_eq!(pv.terminal, None);
+    assert!(pv.depth > 1);
+}
+
+// Share of the root visits taken by the most visited move
+fn top_share(results: &[(ChessMove, f32)]) -> f32 {
+    let top = results
+        .iter()
+        .map(|(_, visits)| *visits)
+        .fold(0.0, f32::max);
+    top / visits(results)
+}
+
+#[test]
+fn uniform_prior_mix_flattens_visits() {
+    // Captures and checks are available, so the priors are far from uniform
+    let board =
+        Board::from_str("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4")
+            .unwrap();
+    let share = |mix: f32| {
+        let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.0)
+            .with_seed(SEED)
+            .with_early_exit(false)
+            .with_prior_uniform_mix(mix);
+        top_share(&tree.search(board, Limit::new(None, Some(2000.0), None)))
+    };
+    assert!(share(1.0) < share(0.0));
+}
+
+// Search `fen` with a fixed budget, for the root statistics of the tree
+fn searched(fen: &str) -> Tree {
+    let board = Board::from_str(fen).unwrap();
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3).with_seed(SEED);
+    tree.search(board, Limit::new(None, Some(2000.0), None));
+    tree
+}
+
+// Black has just taken a knight, only dxc3 wins the bishop back
+const RECAPTURE: &str = "4k3/pp6/8/8/8/2b5/P2P4/4K3 w - - 0 1";
+
+#[test]
+fn singular_recapture() {
+    let tree = searched(RECAPTURE);
+    assert_eq!(uci(&tree.principal_variation().moves[0]), "d2c3");
+    assert!(tree.is_singular(200));
+
+    let tree = searched(&Board::default().to_string());
+    assert!(!tree.is_singular(200));
+}
+
+#[test]
+fn pessimistic_when_losing_narrows_search() {
+    // A queen down with nothing to win back, so every move is losing
+    let board = Board::from_str("6k1/5ppp/8/8/8/8/q4PPP/1R4K1 w - - 0 1").unwrap();
+    let search = |pessimistic: bool| {
+        let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+            .with_seed(SEED)
+            .with_early_exit(false)
+            .with_pessimistic_when_losing(pessimistic);
+        let results = tree.search(board, Limit::new(None, Some(3000.0), None));
+        let explored = results.iter().filter(|(_, visits)| *visits > 0.0).count();
+        (explored, top_share(&results))
+    };
+    let (spread, spread_share) = search(false);
+    let (narrow, narrow_share) = search(true);
+    assert!(narrow < spread);
+    assert!(narrow_share > spread_share);
+}
+
+#[test]
+fn first_limit_reached_stops() {
+    let search = |limit: Limit| {
+        let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+            .with_seed(SEED)
+            .with_early_exit(false);
+        let start = Instant::now();
+        let results = tree.search(Board::default(), limit);
+        (visits(&results), start.elapsed().as_secs_f32())
+    };
+    // The node limit comes long before a minute is up
+    let (nodes, elapsed) = search(Limit::new(Some(60.0), Some(500.0), None));
+    assert!(nodes <= 500.0);
+    assert!(elapsed < 30.0);
+    // The time limit comes long before a billion nodes
+    let (nodes, elapsed) = search(Limit::new(Some(0.2), Some(1e9), None));
+    assert!(nodes < 1e9);
+    assert!(elapsed < 5.0);
+}
+
+#[test]
+fn value_spread() {
+    // Every line through the mate backs up the same value
+    let tree = searched("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+    let mate = ChessMove::from_str("a1a8").unwrap();
+    assert!(tree.value_std_dev(mate).unwrap() < 0.01);
+
+    // Lines in a fight between queen and rook swing with each capture
+    let tree = searched("4k3/8/8/3q4/8/8/3P4/3RK3 w - - 0 1");
+    let best = tree.principal_variation().moves[0];
+    assert!(tree.value_std_dev(best).unwrap() > 1.0);
+}
+
+#[test]
+fn inferior_to_recapture() {
+    let tree = searched(RECAPTURE);
+    let explored = tree
+        .policy_target()
+        .iter()
+        .filter(|(_, share)| *share > 0.0)
+        .count();
+    let inferior = tree.inferior_moves(200);
+    assert_eq!(inferior.len(), explored - 1);
+    assert!(inferior
+        .iter()
+        .all(|(action, loss)| uci(action) != "d2c3" && *loss > 200));
+}
+
+#[test]
+fn winning_capture_has_positive_value() {
+    // Each side takes a hanging queen
+    for (fen, capture) in [
+        ("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", "d1d5"),
+        ("4k3/p7/8/8/8/2b5/8/Q6K b - - 0 1", "c3a1"),
+    ]
+    .iter()
+    {
+        let tree = searched(fen);
+        let action = ChessMove::from_str(capture).unwrap();
+        assert_eq!(tree.principal_variation().moves[0], action);
+        assert!(tree.expected_value(action).unwrap() > 1.0, "{}", fen);
+    }
+}
+
+#[test]
+fn unvisited_moves_by_prior() {
+    let board = Board::from_str(RECAPTURE).unwrap();
+    let priors = Evaluator::new().priors(&board);
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.0)
+        .with_seed(SEED)
+        .with_early_exit(false)
+        .with_trajectory(true);
+    tree.search(board, Limit::new(None, Some(500.0), None));
+
+    // Root moves in the order they were first tried
+    let mut tried: Vec<ChessMove> = vec![];
+    for step in tree.trajectory().unwrap() {
+        if let Some(action) = step.path.first() {
+            if !tried.contains(action) {
+                tried.push(*action);
+            }
+        }
+    }
+    assert_eq!(tried.len(), priors.len());
+    assert!(tried
+        .windows(2)
+        .all(|pair| priors[&pair[0]] >= priors[&pair[1]]));
+}
+
+#[test]
+fn policy_target_covers_legal_moves() {
+    // Fewer nodes than moves, so some moves are never visited
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3).with_seed(SEED);
+    tree.search(Board::default(), Limit::new(None, Some(10.0), None));
+    let policy = tree.policy_target();
+    assert_eq!(policy.len(), 20);
+    assert!(policy.iter().any(|(_, share)| *share == 0.0));
+    let total: f32 = policy.iter().map(|(_, share)| share).sum();
+    assert!((total - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn every_move_gets_mated() {
+    // Each of Black's moves allows mate in one, so the search reaches nodes
+    // without legal moves
+    let tree = searched("k7/2K5/8/6p1/8/8/8/7R b - - 0 1");
+    assert_eq!(
+        tree.principal_variation().terminal,
+        Some(Outcome::Checkmate)
+    );
+}
+
+#[test]
+fn cache_follows_weight_changes() {
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+        .with_seed(SEED)
+        .with_eval_cache(DEFAULT_CACHE_CAPACITY);
+    let limit = Limit::new(None, Some(500.0), None);
+    tree.search(Board::default(), limit);
+    // Searching the same position again finds much of it in the cache
+    tree.search(Board::default(), limit);
+    let repeated = tree.cache_hit_rate();
+    assert!(repeated > 0.4);
+
+    // New weights make every cached evaluation stale
+    tree.evaluator_mut().set_check_prior_bonus(0.0);
+    tree.search(Board::default(), limit);
+    assert!(tree.cache_hit_rate() < repeated / 2.0);
+}
+
+#[test]
+fn capture_search_is_bounded() {
+    // Both sides pile pieces onto d5, resolving every exchange order there
+    // would take far longer than the node budget allows
+    let board =
+        Board::from_str("1k1r3r/2q2p2/2nb1n2/3p4/1b1Q4/2N1BN2/3R1P2/1K1R4 w - - 0 1").unwrap();
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+        .with_seed(SEED)
+        .with_quiescence_depth(32)
+        .with_quiescence_nodes(64);
+    let start = Instant::now();
+    let results = tree.search(board, Limit::new(None, Some(200.0), None));
+    assert!(start.elapsed().as_secs_f32() < 10.0);
+    for (action, _) in results.iter().filter(|(_, visits)| *visits > 0.0) {
+        let value = tree.expected_value(*action).unwrap();
+        assert!(value.abs() < -MATE_VALUE, "{} {}", uci(action), value);
+    }
+}
+
+#[test]
+fn dead_draws_not_expanded() {
+    // Taking the rook leaves bare kings, nothing below it is searched
+    let tree = searched("4k3/8/8/8/8/8/5r2/4K3 w - - 0 1");
+    let pv = tree.principal_variation();
+    assert_eq!(pv.moves.iter().map(uci).collect::<Vec<_>>(), ["e1f2"]);
+    assert_eq!(pv.terminal, None);
+}
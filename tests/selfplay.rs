@@ -0,0 +1,102 @@
+use chess::{Action, Board, ChessMove, Game};
+use mcts_rust::{
+    eval::Evaluator,
+    selfplay::{eval_vs_search_agreement, play_fixed_nodes_game, play_match, self_play_balance},
+};
+use std::{str::FromStr, sync::Arc};
+
+const SEED: u64 = 0;
+
+#[test]
+fn hand_tuned_beats_material() {
+    let hand_tuned = Arc::new(Evaluator::new());
+    let material = Arc::new(Evaluator::material_only());
+    // Enough games that a short run of lucky ones for the baseline can't decide it
+    assert!(play_match(&hand_tuned, &material, 48) > 0.5);
+}
+
+#[test]
+fn start_position_is_balanced() {
+    let evaluator = Arc::new(Evaluator::new());
+    let openings = [Board::default().to_string()];
+    let report = self_play_balance(Arc::clone(&evaluator), &openings, 12, SEED).unwrap();
+    assert_eq!(report.games, 12);
+    assert!((report.white + report.black + report.draws - 1.0).abs() < 1e-5);
+    assert!((report.white - report.black).abs() <= 0.5);
+
+    // The same seed plays the same games
+    let again = self_play_balance(evaluator, &openings, 12, SEED).unwrap();
+    assert_eq!(
+        (report.white, report.black, report.draws),
+        (again.white, again.black, again.draws)
+    );
+}
+
+// Moves of a game, in order
+fn moves(game: &Game) -> Vec<ChessMove> {
+    game.actions()
+        .iter()
+        .filter_map(|action| match action {
+            Action::MakeMove(action) => Some(*action),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn random_openings_diverge() {
+    let evaluator = Arc::new(Evaluator::new());
+    let opening = |seed: u64| {
+        let game = play_fixed_nodes_game(
+            Arc::clone(&evaluator),
+            Arc::clone(&evaluator),
+            Board::default(),
+            50,
+            4,
+            0.0,
+            4,
+            seed,
+        );
+        moves(&game)
+    };
+    assert_eq!(opening(SEED).len(), 4);
+    assert!((1..10).any(|seed| opening(SEED + seed) != opening(SEED)));
+}
+
+#[test]
+fn fixed_nodes_games_repeat() {
+    let evaluator = Arc::new(Evaluator::new());
+    let play = || {
+        let game = play_fixed_nodes_game(
+            Arc::clone(&evaluator),
+            Arc::clone(&evaluator),
+            Board::default(),
+            200,
+            4,
+            1.0,
+            40,
+            SEED,
+        );
+        moves(&game)
+    };
+    let first = play();
+    assert!(!first.is_empty());
+    assert_eq!(first, play());
+}
+
+#[test]
+fn agreement_on_hanging_queens() {
+    // Taking the queen is best both statically and after searching
+    let positions: Vec<Board> = [
+        "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1",
+        "4k3/p7/8/8/8/2b5/8/Q6K b - - 0 1",
+    ]
+    .iter()
+    .map(|fen| Board::from_str(fen).unwrap())
+    .collect();
+    let evaluator = Evaluator::new();
+    assert_eq!(
+        eval_vs_search_agreement(&evaluator, &positions, 500, SEED),
+        1.0
+    );
+}
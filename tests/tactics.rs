@@ -0,0 +1,190 @@
+use chess::{Board, ChessMove};
+use mcts_rust::{
+    eval::{Evaluator, MATE_VALUE},
+    mcts::{Limit, Tree},
+    uci,
+};
+use ordered_float::OrderedFloat;
+use std::{f32::consts::SQRT_2, str::FromStr, time::Instant};
+
+// Fixed budget and seed so the results don't depend on the machine
+const NODES: f32 = 2000.0;
+const SEED: u64 = 0;
+
+// (fen, best move)
+const POSITIONS: [(&str, &str); 10] = [
+    // Back rank mates
+    ("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"),
+    ("r5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1", "a8a1"),
+    ("7k/8/6K1/8/8/8/8/R7 w - - 0 1", "a1a8"),
+    // Scholar's mate
+    (
+        "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+        "h5f7",
+    ),
+    // Smothered mate
+    ("6rk/6pp/8/6N1/8/8/8/6K1 w - - 0 1", "g5f7"),
+    // Hanging pieces
+    ("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", "d1d5"),
+    ("4k3/8/8/8/8/2b5/8/Q6K b - - 0 1", "c3a1"),
+    // Knight fork of king and rook
+    ("r3k3/8/8/3N4/8/8/8/4K3 w - - 0 1", "d5c7"),
+    // Promotion
+    ("8/P7/8/8/8/8/k7/7K w - - 0 1", "a7a8q"),
+    ("7k/8/8/8/8/8/p7/6K1 b - - 0 1", "a2a1q"),
+];
+
+// (fen, mating move), for both colors so a sign error on either side shows
+const MATES_IN_ONE: [(&str, &str); 4] = [
+    ("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"),
+    ("r5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1", "a8a1"),
+    ("6rk/6pp/8/6N1/8/8/8/6K1 w - - 0 1", "g5f7"),
+    (
+        "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2",
+        "d8h4",
+    ),
+];
+
+// Most visited move and its expected value for the side to move
+fn search(fen: &str) -> (String, f32) {
+    let board = Board::from_str(fen).unwrap();
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3).with_seed(SEED);
+    let results = tree.search(board, Limit::new(None, Some(NODES), None));
+    let (action, _) = results
+        .iter()
+        .max_by_key(|(_, visits)| OrderedFloat(*visits))
+        .unwrap();
+    (uci(action), tree.expected_value(*action).unwrap())
+}
+
+fn best_move(fen: &str) -> String {
+    search(fen).0
+}
+
+#[test]
+fn tactics() {
+    let mut failed = vec![];
+    for (fen, expected) in POSITIONS.iter() {
+        let found = best_move(fen);
+        if found != *expected {
+            println!("{} | expected {} | found {}", fen, expected, found);
+            failed.push(*fen);
+        }
+    }
+
+    let passed = POSITIONS.len() - failed.len();
+    println!("{}/{} positions solved", passed, POSITIONS.len());
+    assert!(failed.is_empty(), "failed positions: {:?}", failed);
+}
+
+#[test]
+fn mate_in_one_value() {
+    for (fen, expected) in MATES_IN_ONE.iter() {
+        let (found, value) = search(fen);
+        assert_eq!(found, *expected, "{}", fen);
+        // Mating is worth the full mate value to the side that mates
+        assert!(value >= -MATE_VALUE * 0.9, "{} | value {}", fen, value);
+    }
+}
+
+// Hashes of the positions of a game that shuffled `moves` from `fen` twice,
+// back to where it started, and the position reached
+fn repeated(fen: &str, moves: &[&str]) -> (Board, Vec<u64>) {
+    let mut board = Board::from_str(fen).unwrap();
+    let mut history = vec![];
+    for text in moves.iter().chain(moves.iter()) {
+        history.push(board.get_hash());
+        board = board.make_move_new(ChessMove::from_str(text).unwrap());
+    }
+    (board, history)
+}
+
+#[test]
+fn claim_draw_when_losing() {
+    let claims = |fen: &str, shuffle: &[&str]| {
+        let (board, history) = repeated(fen, shuffle);
+        let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+            .with_seed(SEED)
+            .with_draw_claims(true);
+        tree.search_with_history(board, &history, Limit::new(None, Some(NODES), None));
+        tree.claims_draw()
+    };
+    // A queen down, the third repetition is better than playing on
+    assert!(claims(
+        "6k1/5pp1/7p/8/8/8/q4PPP/1R4K1 w - - 0 1",
+        &["b1c1", "a2a3", "c1b1", "a3a2"]
+    ));
+    // A queen up, the game goes on
+    assert!(!claims(
+        "1r4k1/Q4pp1/7p/8/8/8/5PPP/6K1 w - - 0 1",
+        &["a7a6", "b8c8", "a6a7", "c8b8"]
+    ));
+    // Without the repetitions there is nothing to claim
+    let board = Board::from_str("6k1/5pp1/7p/8/8/8/q4PPP/1R4K1 w - - 0 1").unwrap();
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+        .with_seed(SEED)
+        .with_draw_claims(true);
+    tree.search(board, Limit::new(None, Some(NODES), None));
+    assert!(!tree.claims_draw());
+}
+
+#[test]
+fn claiming_keeps_to_the_time_limit() {
+    // The claim is selected over and over, the search must still stop on time
+    let (board, history) = repeated(
+        "6k1/5pp1/7p/8/8/8/q4PPP/1R4K1 w - - 0 1",
+        &["b1c1", "a2a3", "c1b1", "a3a2"],
+    );
+    let mut tree = Tree::new(Evaluator::new(), SQRT_2, 0.3)
+        .with_seed(SEED)
+        .with_early_exit(false)
+        .with_draw_claims(true);
+    let start = Instant::now();
+    tree.search_with_history(board, &history, Limit::new(Some(0.2), None, None));
+    assert!(start.elapsed().as_secs_f32() < 2.0);
+    assert!(tree.claims_draw());
+}
+
+// Fewest nodes, counting up one at a time, after which the most visited move
+// is `expected`
+fn nodes_to_solve(evaluator: &Evaluator, fen: &str, expected: &str) -> f32 {
+    let board = Board::from_str(fen).unwrap();
+    let mut nodes = 1.0;
+    while nodes < NODES {
+        let mut tree = Tree::new(evaluator.clone(), SQRT_2, 0.3)
+            .with_seed(SEED)
+            .with_early_exit(false);
+        let results = tree.search(board, Limit::new(None, Some(nodes), None));
+        let (action, _) = results
+            .iter()
+            .max_by_key(|(_, visits)| OrderedFloat(*visits))
+            .unwrap();
+        if uci(action) == expected {
+            break;
+        }
+        nodes += 1.0;
+    }
+    nodes
+}
+
+#[test]
+fn forcing_priors_solve_faster() {
+    let boosted = Evaluator::new();
+    let mut unboosted = Evaluator::new();
+    unboosted.set_forcing_prior_factors(1.0, 1.0);
+    let (mut with_boost, mut without_boost) = (0.0, 0.0);
+    for (fen, expected) in POSITIONS.iter() {
+        let (with, without) = (
+            nodes_to_solve(&boosted, fen, expected),
+            nodes_to_solve(&unboosted, fen, expected),
+        );
+        println!("{} | boosted {} | unboosted {}", fen, with, without);
+        with_boost += with;
+        without_boost += without;
+    }
+    println!(
+        "nodes to solve | boosted {} | unboosted {}",
+        with_boost, without_boost
+    );
+    assert!(with_boost < without_boost);
+}
@@ -0,0 +1,94 @@
+"""Tests of the Python module, run against a built mcts_rust with
+`python -m unittest discover tests`, see the README"""
+import os
+import struct
+import tempfile
+import unittest
+
+import mcts_rust
+
+START = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+
+
+class PerftTest(unittest.TestCase):
+    def test_start_position(self):
+        self.assertEqual(mcts_rust.perft(START, 3), 8902)
+
+
+class SearchTreeTest(unittest.TestCase):
+    def test_book_move(self):
+        # Polyglot key of the start position and e2e4 packed as to, from squares
+        e2e4 = 4 | (3 << 3) | (4 << 6) | (1 << 9)
+        entry = struct.pack(">QHHI", 0x463B96181691FC9C, e2e4, 1, 0)
+        with tempfile.TemporaryDirectory() as directory:
+            path = os.path.join(directory, "book.bin")
+            with open(path, "wb") as book:
+                book.write(entry)
+            for _ in range(2):
+                result = mcts_rust.search_tree(START, 0.05, 1.41, 1, book=path)
+                self.assertTrue(result.book)
+                self.assertEqual(result.best_move, "e2e4")
+
+        result = mcts_rust.search_tree(START, 0.0, 1.41, 1, nodes=200)
+        self.assertFalse(result.book)
+        self.assertEqual(result.best_move, result.visits[0][0])
+
+    def test_thread_stats(self):
+        result = mcts_rust.search_tree(START, 0.0, 1.41, 2, nodes=400)
+        self.assertEqual(len(result.threads), 2)
+        # Every leaf outside the cache was evaluated by one of the threads
+        self.assertGreater(sum(nodes for _, nodes in result.threads), 0)
+
+        result = mcts_rust.search_tree(START, 0.0, 1.41, 1, nodes=200)
+        self.assertEqual(len(result.threads), 1)
+        self.assertIn(result.threads[0][0], [action for action, _ in result.visits])
+
+    @unittest.skipUnless(os.path.isdir("/proc/self/task"), "needs procfs")
+    def test_pool_reused(self):
+        # Searches with as many processes share one pool rather than spawning threads
+        mcts_rust.search_tree(START, 0.0, 1.41, 3, nodes=100)
+        threads = set(os.listdir("/proc/self/task"))
+        for _ in range(3):
+            mcts_rust.search_tree(START, 0.0, 1.41, 3, nodes=100)
+            mcts_rust.analyze(START, 0.05, 1.41, 3)
+        self.assertEqual(set(os.listdir("/proc/self/task")), threads)
+
+
+class AnalyzeTest(unittest.TestCase):
+    def test_shared_tree(self):
+        results = mcts_rust.analyze(START, 0.2, 1.41, 2)
+        self.assertEqual(len(results), 20)
+        visits = [visits for _, visits, _ in results]
+        self.assertEqual(visits, sorted(visits, reverse=True))
+        for _, _, value in results:
+            self.assertLess(abs(value), 1.0)
+
+
+class SearchMovesTest(unittest.TestCase):
+    def test_start_position(self):
+        result = mcts_rust.search_moves([], 0.1, noise=0.0)
+        self.assertEqual(result.fen, START)
+        self.assertEqual(len(result.visits), 20)
+        self.assertEqual(result.best_move, result.visits[0][0])
+
+        # Boards don't keep the move counters, so only the position is compared
+        result = mcts_rust.search_moves(["e2e4", "e7e5"], 0.1)
+        self.assertEqual(
+            result.fen.split()[:4],
+            ["rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR", "w", "KQkq", "-"],
+        )
+
+
+class SearcherTest(unittest.TestCase):
+    def test_cache_carries_over(self):
+        # Without the tree, the next position's search finds what the last evaluated
+        searcher = mcts_rust.Searcher(1.41, 0.3, reuse_tree=False)
+        searcher.search(START, 0.2)
+        first = searcher.cache_hit_rate()
+        line = searcher.principal_variation()[0][:2]
+        searcher.search(START, 0.2, line)
+        self.assertGreater(searcher.cache_hit_rate(), first)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,108 @@
+use chess::{Board, ChessMove};
+use mcts_rust::{from_king_takes_rook, uci_move};
+use std::{
+    io::{BufRead, BufReader, Lines, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    str::FromStr,
+};
+
+// The UCI loop of the binary, talked to over its stdin and stdout
+struct Engine {
+    process: Child,
+    stdin: ChildStdin,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl Engine {
+    fn start() -> Engine {
+        let mut process = Command::new(env!("CARGO_BIN_EXE_botfjord"))
+            .arg("uci")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = process.stdin.take().unwrap();
+        let lines = BufReader::new(process.stdout.take().unwrap()).lines();
+        Engine {
+            process,
+            stdin,
+            lines,
+        }
+    }
+
+    fn send(&mut self, command: &str) {
+        writeln!(self.stdin, "{}", command).unwrap();
+    }
+
+    // Lines printed up to and including the first one starting with `prefix`
+    fn read_until(&mut self, prefix: &str) -> Vec<String> {
+        let mut read = vec![];
+        for line in &mut self.lines {
+            let line = line.unwrap();
+            let done = line.starts_with(prefix);
+            read.push(line);
+            if done {
+                break;
+            }
+        }
+        read
+    }
+
+    // The move of the search started by the last `go`
+    fn best_move(&mut self) -> Option<String> {
+        let line = self.read_until("bestmove").pop()?;
+        line.strip_prefix("bestmove ").map(str::to_string)
+    }
+
+    // `quit` only once the output has been read, as it stops any search
+    fn quit(mut self) {
+        self.send("quit");
+        self.process.wait().unwrap();
+    }
+}
+
+#[test]
+fn repetition_from_position_moves() {
+    // A queen down, White has shuffled the rook often enough that going back
+    // to b1 repeats the position a third time
+    let mut engine = Engine::start();
+    engine.send(
+        "position fen 6k1/5pp1/7p/8/8/8/q4PPP/1R4K1 w - - 0 1 moves b1c1 a2a3 c1b1 a3a2 \
+         b1c1 a2a3 c1b1 a3a2 b1c1 a2a3",
+    );
+    engine.send("go nodes 2000");
+    assert_eq!(engine.best_move().as_deref(), Some("c1b1"));
+    engine.quit();
+}
+
+#[test]
+fn answers_while_searching() {
+    let mut engine = Engine::start();
+    engine.send("position startpos");
+    engine.send("go infinite");
+    engine.send("isready");
+    let lines = engine.read_until("readyok");
+    assert!(lines.iter().all(|line| !line.starts_with("bestmove")));
+    engine.send("stop");
+    assert!(engine.best_move().is_some());
+    engine.quit();
+}
+
+#[test]
+fn move_formatting() {
+    let promotion = Board::from_str("8/4P3/8/8/8/k7/8/4K3 w - - 0 1").unwrap();
+    let queen = ChessMove::from_str("e7e8q").unwrap();
+    assert_eq!(uci_move(&promotion, &queen, false), "e7e8q");
+    assert_eq!(uci_move(&promotion, &queen, true), "e7e8q");
+
+    // Castling is the king's two square move in standard chess, and the king
+    // taking its own rook in Chess960
+    let castling = Board::from_str("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    for (king_move, takes_rook) in [("e1g1", "e1h1"), ("e1c1", "e1a1")].iter() {
+        let action = ChessMove::from_str(king_move).unwrap();
+        assert_eq!(uci_move(&castling, &action, false), *king_move);
+        assert_eq!(uci_move(&castling, &action, true), *takes_rook);
+        let parsed = ChessMove::from_str(takes_rook).unwrap();
+        assert_eq!(from_king_takes_rook(&castling, parsed), action);
+    }
+}